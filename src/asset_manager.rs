@@ -0,0 +1,455 @@
+use std::{collections::HashMap, fs, marker::PhantomData, time::SystemTime};
+
+use crate::{
+    mesh::{load_obj_mesh, Mesh},
+    texture::{load_checkerboard_texture, load_texture, Texture},
+};
+
+/// An asset type that the AssetManager can load from a file
+pub trait Asset: Sized {
+    fn load(path: &String) -> Result<Self, String>;
+
+    /// Other files the asset was built from. They are watched for changes
+    /// along with the asset's own file.
+    fn dependencies(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl Asset for Mesh {
+    fn load(path: &String) -> Result<Self, String> {
+        load_obj_mesh(path)
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.material_library_paths.clone()
+    }
+}
+
+impl Asset for Texture {
+    fn load(path: &String) -> Result<Self, String> {
+        load_texture(path)
+    }
+}
+
+/// A reference to an asset owned by the AssetManager. Handles stay valid
+/// until the asset is released; a handle to a released asset is detected
+/// rather than reading whatever asset reuses its slot.
+pub struct Handle<T> {
+    index: usize,
+    generation: u32,
+    asset_type: PhantomData<T>,
+}
+
+pub type MeshHandle = Handle<Mesh>;
+pub type TextureHandle = Handle<Texture>;
+
+impl<T> Handle<T> {
+    /// A handle that never refers to an asset
+    pub fn invalid() -> Self {
+        Self {
+            index: usize::MAX,
+            generation: 0,
+            asset_type: PhantomData,
+        }
+    }
+}
+
+// Implemented by hand since deriving would require T to implement these too
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Default for Handle<T> {
+    fn default() -> Self {
+        Self::invalid()
+    }
+}
+
+struct AssetSlot<T> {
+    asset: Option<T>,
+    /// Incremented every time the slot is freed so that old handles to it can
+    /// be detected
+    generation: u32,
+    /// The file the asset was loaded from, if any
+    path: Option<String>,
+    /// The asset's file and its dependencies, along with their modification
+    /// times when the asset was last loaded
+    watched_files: Vec<WatchedFile>,
+    ref_count: usize,
+}
+
+struct WatchedFile {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn new(path: String) -> Self {
+        let modified = read_modified_time(&path);
+        Self { path, modified }
+    }
+}
+
+/// Returns None if the file doesn't exist or its modification time can't be
+/// read
+fn read_modified_time(path: &String) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Lists the files to watch for an asset loaded from 'path'
+fn watch_files<T: Asset>(path: &String, asset: &T) -> Vec<WatchedFile> {
+    std::iter::once(path.clone())
+        .chain(asset.dependencies())
+        .map(WatchedFile::new)
+        .collect()
+}
+
+/// Stores assets of one type, deduplicated by path and reference counted
+struct AssetStore<T> {
+    slots: Vec<AssetSlot<T>>,
+    free_slots: Vec<usize>,
+    handles_by_path: HashMap<String, Handle<T>>,
+}
+
+impl<T: Asset> AssetStore<T> {
+    fn new() -> Self {
+        Self {
+            slots: vec![],
+            free_slots: vec![],
+            handles_by_path: HashMap::new(),
+        }
+    }
+
+    fn get_slot(&self, handle: Handle<T>) -> Option<&AssetSlot<T>> {
+        match self.slots.get(handle.index) {
+            Some(slot)
+                if slot.generation == handle.generation
+                    && slot.asset.is_some() =>
+            {
+                Some(slot)
+            }
+            _ => None,
+        }
+    }
+
+    fn get_slot_mut(&mut self, handle: Handle<T>) -> Option<&mut AssetSlot<T>> {
+        match self.slots.get_mut(handle.index) {
+            Some(slot)
+                if slot.generation == handle.generation
+                    && slot.asset.is_some() =>
+            {
+                Some(slot)
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.get_slot(handle)?.asset.as_ref()
+    }
+
+    /// Adds an asset with a reference count of 1
+    fn add(&mut self, asset: T, path: Option<String>) -> Handle<T> {
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(AssetSlot {
+                    asset: None,
+                    generation: 0,
+                    path: None,
+                    watched_files: vec![],
+                    ref_count: 0,
+                });
+                self.slots.len() - 1
+            }
+        };
+
+        let slot = &mut self.slots[index];
+        slot.watched_files = match &path {
+            Some(path) => watch_files(path, &asset),
+            None => vec![],
+        };
+        slot.asset = Some(asset);
+        slot.path = path.clone();
+        slot.ref_count = 1;
+
+        let handle = Handle {
+            index,
+            generation: slot.generation,
+            asset_type: PhantomData,
+        };
+        if let Some(path) = path {
+            self.handles_by_path.insert(path, handle);
+        }
+
+        handle
+    }
+
+    /// Returns a new reference to the asset loaded from 'path', or loads it
+    /// if it hasn't been loaded yet
+    fn load(&mut self, path: &String) -> Result<Handle<T>, String> {
+        // Different paths may name the same file
+        let key = match fs::canonicalize(path) {
+            Ok(canonical_path) => canonical_path.to_string_lossy().to_string(),
+            Err(_) => path.clone(),
+        };
+
+        if let Some(handle) = self.handles_by_path.get(&key).copied() {
+            self.retain(handle);
+            return Ok(handle);
+        }
+
+        let asset = T::load(path)?;
+        Ok(self.add(asset, Some(key)))
+    }
+
+    fn retain(&mut self, handle: Handle<T>) {
+        if let Some(slot) = self.get_slot_mut(handle) {
+            slot.ref_count += 1;
+        }
+    }
+
+    /// Drops a reference to an asset, freeing it when no references remain
+    fn release(&mut self, handle: Handle<T>) {
+        let slot = match self.get_slot_mut(handle) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        slot.ref_count -= 1;
+        if slot.ref_count > 0 {
+            return;
+        }
+
+        slot.asset = None;
+        slot.watched_files.clear();
+        slot.generation = slot.generation.wrapping_add(1);
+        if let Some(path) = slot.path.take() {
+            self.handles_by_path.remove(&path);
+        }
+        self.free_slots.push(handle.index);
+    }
+
+    /// Reloads every asset whose file or dependencies were modified since it
+    /// was last loaded. Handles to reloaded assets stay valid. If an asset
+    /// fails to reload the previous version is kept, and the reload is tried
+    /// again next time since the files may have been only partly written.
+    fn reload_changed(&mut self) {
+        for slot in &mut self.slots {
+            let (asset, path) = match (&mut slot.asset, &slot.path) {
+                (Some(asset), Some(path)) => (asset, path),
+                _ => continue,
+            };
+
+            // Modification times are only updated once the reload succeeds
+            let changed = slot.watched_files.iter().any(|watched_file| {
+                read_modified_time(&watched_file.path) != watched_file.modified
+            });
+            if !changed {
+                continue;
+            }
+
+            match T::load(path) {
+                Ok(reloaded_asset) => {
+                    slot.watched_files = watch_files(path, &reloaded_asset);
+                    *asset = reloaded_asset;
+                    println!("Reloaded {}", path);
+                }
+                Err(error) => {
+                    println!(
+                        "{}. Keeping the previously loaded {}",
+                        error, path
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Owns every mesh and texture. Assets loaded from the same path are only
+/// loaded once and are shared by every handle to them.
+pub struct AssetManager {
+    meshes: AssetStore<Mesh>,
+    textures: AssetStore<Texture>,
+    /// Used in place of textures that failed to load or whose handles are
+    /// invalid
+    missing_texture: Texture,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self {
+            meshes: AssetStore::new(),
+            textures: AssetStore::new(),
+            missing_texture: load_checkerboard_texture(),
+        }
+    }
+
+    /// Loads an OBJ mesh, or adds a reference to it if it was already loaded
+    pub fn load_mesh(&mut self, path: &String) -> Result<MeshHandle, String> {
+        self.meshes.load(path)
+    }
+
+    /// Loads a texture, or adds a reference to it if it was already loaded
+    pub fn load_texture(
+        &mut self,
+        path: &String,
+    ) -> Result<TextureHandle, String> {
+        self.textures.load(path)
+    }
+
+    /// Adds a mesh that wasn't loaded from a file
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshHandle {
+        self.meshes.add(mesh, None)
+    }
+
+    /// Adds a texture that wasn't loaded from a file
+    pub fn add_texture(&mut self, texture: Texture) -> TextureHandle {
+        self.textures.add(texture, None)
+    }
+
+    /// Drops a reference to a mesh, freeing it when no references remain
+    pub fn release_mesh(&mut self, handle: MeshHandle) {
+        self.meshes.release(handle);
+    }
+
+    /// Drops a reference to a texture, freeing it when no references remain
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        self.textures.release(handle);
+    }
+
+    /// Gets a mesh. Returns None if the handle is invalid.
+    pub fn get_mesh(&self, handle: MeshHandle) -> Option<&Mesh> {
+        self.meshes.get(handle)
+    }
+
+    /// Gets a texture. Returns the missing texture checkerboard if the handle
+    /// is invalid.
+    pub fn get_texture(&self, handle: TextureHandle) -> &Texture {
+        match self.textures.get(handle) {
+            Some(texture) => texture,
+            None => &self.missing_texture,
+        }
+    }
+
+    /// Reloads every mesh and texture whose files changed on disk
+    pub fn reload_changed_assets(&mut self) {
+        self.meshes.reload_changed();
+        self.textures.reload_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::File, path::PathBuf, ptr, time::Duration};
+
+    use super::*;
+    use crate::{mesh::load_test_mesh, texture::load_test_texture};
+
+    /// An asset that is the contents of a text file. Files that don't
+    /// start with "ok" fail to load.
+    struct TextAsset(String);
+
+    impl Asset for TextAsset {
+        fn load(path: &String) -> Result<Self, String> {
+            let contents = fs::read_to_string(path).map_err(|error| {
+                format!("Unable to read {}: {}", path, error)
+            })?;
+            if contents.starts_with("ok") {
+                Ok(TextAsset(contents))
+            } else {
+                Err(format!("Bad contents in {}", path))
+            }
+        }
+    }
+
+    /// Writes a file in the temporary directory and sets its modification
+    /// time to 'seconds' past the epoch, so changes are seen regardless of
+    /// the file system's timestamp resolution
+    fn write_file(file_name: &str, contents: &str, seconds: u64) -> String {
+        let path: PathBuf = env::temp_dir().join(file_name);
+        fs::write(&path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn release_frees_asset_after_last_reference() {
+        let path = write_file("asset_manager_release.txt", "ok", 1000);
+        let mut store = AssetStore::<TextAsset>::new();
+
+        let handle = store.load(&path).unwrap();
+        assert!(store.load(&path).unwrap() == handle);
+
+        store.release(handle);
+        assert!(store.get(handle).is_some());
+
+        store.release(handle);
+        assert!(store.get(handle).is_none());
+        assert!(store.handles_by_path.is_empty());
+
+        // The freed slot is reused, but the old handle stays invalid
+        let new_handle = store.load(&path).unwrap();
+        assert_eq!(new_handle.index, handle.index);
+        assert!(new_handle != handle);
+        assert!(store.get(handle).is_none());
+    }
+
+    #[test]
+    fn release_mesh_and_texture_free_added_assets() {
+        let mut assets = AssetManager::new();
+        let mesh_handle = assets.add_mesh(load_test_mesh());
+        let texture_handle = assets.add_texture(load_test_texture());
+        assert!(assets.get_mesh(mesh_handle).is_some());
+        assert!(!ptr::eq(
+            assets.get_texture(texture_handle),
+            &assets.missing_texture
+        ));
+
+        // Released textures fall back to the missing texture
+        assets.release_mesh(mesh_handle);
+        assets.release_texture(texture_handle);
+        assert!(assets.get_mesh(mesh_handle).is_none());
+        assert!(ptr::eq(
+            assets.get_texture(texture_handle),
+            &assets.missing_texture
+        ));
+    }
+
+    #[test]
+    fn failed_reload_is_retried() {
+        let path = write_file("asset_manager_reload.txt", "ok 1", 1000);
+        let mut store = AssetStore::<TextAsset>::new();
+        let handle = store.load(&path).unwrap();
+
+        // A half-written file fails to load and the old asset is kept
+        write_file("asset_manager_reload.txt", "o", 2000);
+        store.reload_changed();
+        assert_eq!(store.get(handle).unwrap().0, "ok 1");
+
+        // Once the file is complete it is picked up, even if its
+        // modification time didn't change again
+        write_file("asset_manager_reload.txt", "ok 2", 2000);
+        store.reload_changed();
+        assert_eq!(store.get(handle).unwrap().0, "ok 2");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::texture::{pack_argb, read_u16_le, read_u32_le, Texture};
+
+const FILE_HEADER_SIZE: usize = 14;
+
+/// Compression values from the info header
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+/// Decodes an uncompressed 24 or 32 bit BMP image
+pub fn decode_bmp(data: &[u8]) -> Result<Texture, String> {
+    if !data.starts_with(b"BM") {
+        return Err("Missing BMP signature".to_string());
+    }
+
+    let pixel_data_offset = read_u32_le(data, 10)? as usize;
+    let info_header_size = read_u32_le(data, FILE_HEADER_SIZE)? as usize;
+    if info_header_size < 40 {
+        return Err(format!(
+            "Unsupported BMP header size {}",
+            info_header_size
+        ));
+    }
+
+    let width = read_u32_le(data, 18)? as i32;
+    let height = read_u32_le(data, 22)? as i32;
+    let bits_per_pixel = read_u16_le(data, 28)?;
+    let compression = read_u32_le(data, 30)?;
+
+    if width <= 0 || height == 0 {
+        return Err(format!("Bad BMP dimensions {}x{}", width, height));
+    }
+    // A negative height means rows are stored from the top down rather than
+    // from the bottom up
+    let top_to_bottom = height < 0;
+    let width = width as usize;
+    let height = height.unsigned_abs() as usize;
+
+    // Each channel is found by masking the pixel value. Without bitfields,
+    // 24 and 32 bit pixels are BGR(X) and the unused byte is not alpha.
+    let masks = match (bits_per_pixel, compression) {
+        (24, BI_RGB) | (32, BI_RGB) => {
+            [0x00FF0000, 0x0000FF00, 0x000000FF, 0x00000000]
+        }
+        (32, BI_BITFIELDS) => {
+            // The masks follow a 40 byte header, or are part of newer headers,
+            // which also include the alpha mask
+            let masks_offset = FILE_HEADER_SIZE + 40;
+            let alpha_mask = if info_header_size >= 56 {
+                read_u32_le(data, masks_offset + 12)?
+            } else {
+                0
+            };
+            [
+                read_u32_le(data, masks_offset)?,
+                read_u32_le(data, masks_offset + 4)?,
+                read_u32_le(data, masks_offset + 8)?,
+                alpha_mask,
+            ]
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported BMP format: {} bits per pixel with compression {}",
+                bits_per_pixel, compression
+            ))
+        }
+    };
+
+    // Rows are padded to a multiple of 4 bytes
+    let bytes_per_pixel = bits_per_pixel as usize / 8;
+    let row_size = (width * bytes_per_pixel).div_ceil(4) * 4;
+
+    let pixel_data_end =
+        row_size.checked_mul(height).and_then(|pixel_data_size| {
+            pixel_data_size.checked_add(pixel_data_offset)
+        });
+    match pixel_data_end {
+        Some(pixel_data_end) if pixel_data_end <= data.len() => {}
+        _ => return Err("BMP pixel data is truncated".to_string()),
+    }
+
+    let mut argb_data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let source_y = if top_to_bottom { y } else { height - 1 - y };
+        let row_offset = pixel_data_offset + source_y * row_size;
+
+        for x in 0..width {
+            let pixel_offset = row_offset + x * bytes_per_pixel;
+            let value = match data
+                .get(pixel_offset..(pixel_offset + bytes_per_pixel))
+            {
+                Some([blue, green, red]) => {
+                    u32::from_le_bytes([*blue, *green, *red, 0])
+                }
+                Some([blue, green, red, fourth]) => {
+                    u32::from_le_bytes([*blue, *green, *red, *fourth])
+                }
+                _ => return Err("BMP pixel data is truncated".to_string()),
+            };
+
+            let red = extract_channel(value, masks[0]);
+            let green = extract_channel(value, masks[1]);
+            let blue = extract_channel(value, masks[2]);
+            let alpha = if masks[3] == 0 {
+                0xFF
+            } else {
+                extract_channel(value, masks[3])
+            };
+            argb_data.push(pack_argb(alpha, red, green, blue));
+        }
+    }
+
+    Ok(Texture::new(width, height, argb_data))
+}
+
+/// Extracts the channel selected by 'mask' from a pixel and scales it to 8
+/// bits
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max_value = (mask >> shift) as u64;
+    let channel = ((value & mask) >> shift) as u64;
+
+    ((channel * 255 + max_value / 2) / max_value) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the headers of an uncompressed 24 bit BMP
+    fn create_header(
+        width: i32,
+        height: i32,
+        pixel_data_offset: u32,
+    ) -> Vec<u8> {
+        let mut header = vec![0; FILE_HEADER_SIZE + 40];
+        header[0..2].copy_from_slice(b"BM");
+        header[10..14].copy_from_slice(&pixel_data_offset.to_le_bytes());
+        header[14..18].copy_from_slice(&40u32.to_le_bytes());
+        header[18..22].copy_from_slice(&width.to_le_bytes());
+        header[22..26].copy_from_slice(&height.to_le_bytes());
+        header[28..30].copy_from_slice(&24u16.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn decode_24_bit_bmp() {
+        // Two rows from the bottom up, each padded to 4 bytes
+        let mut data = create_header(1, 2, (FILE_HEADER_SIZE + 40) as u32);
+        data.extend_from_slice(&[0xFF, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&[0x56, 0x34, 0x12, 0x00]);
+        let texture = decode_bmp(&data).unwrap();
+        assert_eq!(texture.levels[0].data, [0xFF123456, 0xFF0000FF]);
+    }
+
+    #[test]
+    fn decode_bmp_rejects_zero_dimensions() {
+        assert!(decode_bmp(&create_header(0, 4, 54)).is_err());
+        assert!(decode_bmp(&create_header(4, 0, 54)).is_err());
+    }
+
+    #[test]
+    fn decode_bmp_rejects_overflowing_sizes() {
+        assert!(decode_bmp(&create_header(i32::MAX, i32::MIN, 54)).is_err());
+        assert!(decode_bmp(&create_header(i32::MAX, i32::MAX, 54)).is_err());
+        assert!(decode_bmp(&create_header(1, 1, u32::MAX)).is_err());
+    }
+}
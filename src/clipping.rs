@@ -1,6 +1,6 @@
 use crate::{
     texture::TextureUv,
-    triangle::Triangle,
+    triangle::{Triangle, Varyings},
     vector::{Vector3, Vector4},
 };
 
@@ -20,7 +20,7 @@ pub struct FrustumPlanes {
 
 #[derive(Clone, Default)]
 struct Polygon {
-    vertices: Vec<(Vector3, TextureUv)>,
+    vertices: Vec<(Vector3, TextureUv, Varyings)>,
 }
 
 impl Polygon {
@@ -120,6 +120,93 @@ impl FrustumPlanes {
             right_plane,
         }
     }
+
+    /// Makes the planes for an orthographic view volume, which is a box
+    /// centered on the z axis. The normals point to the inside of the box.
+    pub fn new_orthographic(
+        half_width: f32,
+        half_height: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Self {
+        let make_plane =
+            |position: Vector3, normal: Vector3| Plane { position, normal };
+
+        Self {
+            near_plane: make_plane(
+                Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: znear,
+                },
+                Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                },
+            ),
+            far_plane: make_plane(
+                Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: zfar,
+                },
+                Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: -1.0,
+                },
+            ),
+            left_plane: make_plane(
+                Vector3 {
+                    x: -1.0 * half_width,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Vector3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            ),
+            right_plane: make_plane(
+                Vector3 {
+                    x: half_width,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Vector3 {
+                    x: -1.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            ),
+            top_plane: make_plane(
+                Vector3 {
+                    x: 0.0,
+                    y: half_height,
+                    z: 0.0,
+                },
+                Vector3 {
+                    x: 0.0,
+                    y: -1.0,
+                    z: 0.0,
+                },
+            ),
+            bottom_plane: make_plane(
+                Vector3 {
+                    x: 0.0,
+                    y: -1.0 * half_height,
+                    z: 0.0,
+                },
+                Vector3 {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            ),
+        }
+    }
 }
 
 fn clip_polygon_against_plane(polygon: &Polygon, plane: &Plane) -> Polygon {
@@ -128,16 +215,17 @@ fn clip_polygon_against_plane(polygon: &Polygon, plane: &Plane) -> Polygon {
     }
 
     // Vec for tracking vertices of the final polygon that are inside the frustum
-    let mut inside_vertices = Vec::<(Vector3, TextureUv)>::with_capacity(10);
+    let mut inside_vertices =
+        Vec::<(Vector3, TextureUv, Varyings)>::with_capacity(10);
 
     // Track the current and previous vertex so that we know if we've crossed a frustum plane
     let mut current_vertex_index = 0;
     let mut previous_vertex_index = polygon.len() - 1;
 
     while current_vertex_index < polygon.len() {
-        let (current_vertex, current_uv) =
+        let (current_vertex, current_uv, current_varyings) =
             &polygon.vertices[current_vertex_index];
-        let (previous_vertex, previous_uv) =
+        let (previous_vertex, previous_uv, previous_varyings) =
             &polygon.vertices[previous_vertex_index];
 
         let current_dot = Vector3::dot_product(
@@ -160,14 +248,24 @@ fn clip_polygon_against_plane(polygon: &Polygon, plane: &Plane) -> Polygon {
                 u: previous_uv.u + t * (current_uv.u - previous_uv.u),
                 v: previous_uv.v + t * (current_uv.v - previous_uv.v),
             };
+            let intersection_varyings =
+                Varyings::lerp(previous_varyings, current_varyings, t);
 
             // Insert the intersection point to the list of inside vertices
-            inside_vertices.push((intersection_point, intersection_uv));
+            inside_vertices.push((
+                intersection_point,
+                intersection_uv,
+                intersection_varyings,
+            ));
         }
 
         // This vertex is on the inside of the frustum
         if current_dot > 0.0 {
-            inside_vertices.push((current_vertex.clone(), current_uv.clone()));
+            inside_vertices.push((
+                current_vertex.clone(),
+                current_uv.clone(),
+                *current_varyings,
+            ));
         }
 
         current_vertex_index += 1;
@@ -189,14 +287,17 @@ pub fn clip_triangle(
             (
                 Vector3::from_vector4(&triangle.points[0]),
                 triangle.texel_coordinates[0].clone(),
+                triangle.varyings[0],
             ),
             (
                 Vector3::from_vector4(&triangle.points[1]),
                 triangle.texel_coordinates[1].clone(),
+                triangle.varyings[1],
             ),
             (
                 Vector3::from_vector4(&triangle.points[2]),
                 triangle.texel_coordinates[2].clone(),
+                triangle.varyings[2],
             ),
         ],
     };
@@ -226,9 +327,9 @@ pub fn clip_triangle(
                 let index1 = index + 1;
                 let index2 = index + 2;
 
-                let (vertex0, uv0) = &polygon.vertices[index0];
-                let (vertex1, uv1) = &polygon.vertices[index1];
-                let (vertex2, uv2) = &polygon.vertices[index2];
+                let (vertex0, uv0, varyings0) = &polygon.vertices[index0];
+                let (vertex1, uv1, varyings1) = &polygon.vertices[index1];
+                let (vertex2, uv2, varyings2) = &polygon.vertices[index2];
                 let new_triangle = Triangle {
                     points: [
                         Vector4::from_vector3(vertex0),
@@ -236,8 +337,10 @@ pub fn clip_triangle(
                         Vector4::from_vector3(vertex2),
                     ],
                     texel_coordinates: [uv0.clone(), uv1.clone(), uv2.clone()],
+                    varyings: [*varyings0, *varyings1, *varyings2],
+                    material: triangle.material,
                     color: triangle.color,
-                    light_intensity: triangle.light_intensity,
+                    light_color: triangle.light_color,
                     texture_handle: triangle.texture_handle,
                 };
 
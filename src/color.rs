@@ -0,0 +1,202 @@
+use std::ops;
+
+/// An RGBA color with floating point channels. Channels are nominally in
+/// [0.0, 1.0] but may exceed that range while accumulating light. Colors are
+/// saturated when they are converted back to ARGB.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const BLACK: Color = Color {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    pub const WHITE: Color = Color {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+        a: 1.0,
+    };
+
+    /// Creates an opaque color
+    pub fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Self { r, g, b, a: 1.0 }
+    }
+
+    /// Converts a packed ARGB color (as stored in the color buffer) to a Color
+    pub fn from_argb(color: u32) -> Self {
+        Self {
+            r: ((color >> 16) & 0xFF) as f32 / 255.0,
+            g: ((color >> 8) & 0xFF) as f32 / 255.0,
+            b: (color & 0xFF) as f32 / 255.0,
+            a: ((color >> 24) & 0xFF) as f32 / 255.0,
+        }
+    }
+
+    /// Converts the color to packed ARGB. Channels outside of [0.0, 1.0] are
+    /// clamped.
+    pub fn to_argb(self) -> u32 {
+        let color = self.saturate();
+
+        let a = (color.a * 255.0).round() as u32;
+        let r = (color.r * 255.0).round() as u32;
+        let g = (color.g * 255.0).round() as u32;
+        let b = (color.b * 255.0).round() as u32;
+
+        (a << 24) | (r << 16) | (g << 8) | b
+    }
+
+    /// Clamps every channel to [0.0, 1.0]
+    pub fn saturate(&self) -> Self {
+        Self {
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
+            a: self.a.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Returns the same color with a different alpha
+    pub fn with_alpha(&self, a: f32) -> Self {
+        Self { a, ..*self }
+    }
+}
+
+impl ops::Add<Color> for Color {
+    type Output = Color;
+
+    fn add(self, rhs: Color) -> Color {
+        Color {
+            r: self.r + rhs.r,
+            g: self.g + rhs.g,
+            b: self.b + rhs.b,
+            a: self.a + rhs.a,
+        }
+    }
+}
+
+impl ops::Add<&Color> for &Color {
+    type Output = Color;
+
+    fn add(self, rhs: &Color) -> Color {
+        Color {
+            r: self.r + rhs.r,
+            g: self.g + rhs.g,
+            b: self.b + rhs.b,
+            a: self.a + rhs.a,
+        }
+    }
+}
+
+impl ops::Sub<Color> for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Color) -> Color {
+        Color {
+            r: self.r - rhs.r,
+            g: self.g - rhs.g,
+            b: self.b - rhs.b,
+            a: self.a - rhs.a,
+        }
+    }
+}
+
+impl ops::Sub<&Color> for &Color {
+    type Output = Color;
+
+    fn sub(self, rhs: &Color) -> Color {
+        Color {
+            r: self.r - rhs.r,
+            g: self.g - rhs.g,
+            b: self.b - rhs.b,
+            a: self.a - rhs.a,
+        }
+    }
+}
+
+/// Multiplies each channel by the matching channel of the other color. This is
+/// how a surface color is tinted by the color of a light.
+impl ops::Mul<Color> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: Color) -> Color {
+        Color {
+            r: self.r * rhs.r,
+            g: self.g * rhs.g,
+            b: self.b * rhs.b,
+            a: self.a * rhs.a,
+        }
+    }
+}
+
+impl ops::Mul<&Color> for &Color {
+    type Output = Color;
+
+    fn mul(self, rhs: &Color) -> Color {
+        Color {
+            r: self.r * rhs.r,
+            g: self.g * rhs.g,
+            b: self.b * rhs.b,
+            a: self.a * rhs.a,
+        }
+    }
+}
+
+impl ops::Mul<Color> for f32 {
+    type Output = Color;
+
+    fn mul(self, rhs: Color) -> Color {
+        Color {
+            r: self * rhs.r,
+            g: self * rhs.g,
+            b: self * rhs.b,
+            a: self * rhs.a,
+        }
+    }
+}
+
+impl ops::Mul<&Color> for f32 {
+    type Output = Color;
+
+    fn mul(self, rhs: &Color) -> Color {
+        Color {
+            r: self * rhs.r,
+            g: self * rhs.g,
+            b: self * rhs.b,
+            a: self * rhs.a,
+        }
+    }
+}
+
+impl ops::Mul<f32> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: f32) -> Color {
+        Color {
+            r: self.r * rhs,
+            g: self.g * rhs,
+            b: self.b * rhs,
+            a: self.a * rhs,
+        }
+    }
+}
+
+impl ops::Mul<f32> for &Color {
+    type Output = Color;
+
+    fn mul(self, rhs: f32) -> Color {
+        Color {
+            r: self.r * rhs,
+            g: self.g * rhs,
+            b: self.b * rhs,
+            a: self.a * rhs,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{
+    color::Color,
+    render::{draw_rect, draw_shaded_triangle, ColorBuffer},
+    triangle::Triangle,
+};
+
+/// Number of fragments per pixel shown at the hot end of the overdraw
+/// heatmap. Pixels drawn more often than this are shown white.
+const OVERDRAW_MAX_COUNT: u32 = 8;
+/// Screen area in pixels of the largest triangle that gets its own color in
+/// the triangle area view. Larger triangles share the coldest color.
+const TRIANGLE_AREA_MAX: f32 = 4096.0;
+// Size and spacing of legend swatches, in pixels
+const LEGEND_SWATCH_SIZE: i32 = 16;
+const LEGEND_MARGIN: i32 = 8;
+
+// Colors of the false color ramp from 0.0 to 1.0, evenly spaced
+const FALSE_COLOR_RAMP: [Color; 5] = [
+    Color {
+        r: 0.0,
+        g: 0.0,
+        b: 1.0,
+        a: 1.0,
+    },
+    Color {
+        r: 0.0,
+        g: 1.0,
+        b: 1.0,
+        a: 1.0,
+    },
+    Color {
+        r: 0.0,
+        g: 1.0,
+        b: 0.0,
+        a: 1.0,
+    },
+    Color {
+        r: 1.0,
+        g: 1.0,
+        b: 0.0,
+        a: 1.0,
+    },
+    Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    },
+];
+
+/// Replaces the colors of a color buffer with a visualization of its zbuffer.
+/// Depth is linearized between 'znear' and 'zfar', with near surfaces drawn
+/// bright (or red with 'false_color') and far surfaces drawn dark (or blue).
+/// Pixels that nothing was drawn to are black.
+pub fn draw_depth_buffer(
+    color_buffer: &mut ColorBuffer,
+    znear: f32,
+    zfar: f32,
+    false_color: bool,
+) {
+    for index in 0..(color_buffer.width * color_buffer.height) {
+        let reciprocal_z = color_buffer.zbuffer[index];
+        if reciprocal_z <= 0.0 {
+            color_buffer.buffer[index] = 0xFF000000;
+            continue;
+        }
+
+        // The zbuffer holds 1 / w, and w is the view space depth
+        let depth = 1.0 / reciprocal_z;
+        let closeness =
+            1.0 - ((depth - znear) / (zfar - znear)).clamp(0.0, 1.0);
+
+        color_buffer.buffer[index] = if false_color {
+            calc_false_color(closeness).to_argb()
+        } else {
+            Color::rgb(closeness, closeness, closeness).to_argb()
+        };
+    }
+}
+
+/// Maps a value in [0.0, 1.0] to a color ramp running from blue through cyan,
+/// green and yellow to red. Small differences are easier to tell apart than
+/// in grayscale.
+pub fn calc_false_color(value: f32) -> Color {
+    let position = value.clamp(0.0, 1.0) * (FALSE_COLOR_RAMP.len() - 1) as f32;
+    let index = (position as usize).min(FALSE_COLOR_RAMP.len() - 2);
+    let fraction = position - index as f32;
+
+    &((1.0 - fraction) * &FALSE_COLOR_RAMP[index])
+        + &(fraction * &FALSE_COLOR_RAMP[index + 1])
+}
+
+/// Draws a triangle colored by its interpolated surface normal. The normal's
+/// x, y and z in [-1.0, 1.0] are mapped to red, green and blue in [0.0, 1.0].
+/// 'normal_varying' is the index of the normal in the triangle's varyings,
+/// which selects the space the normal is shown in.
+pub fn draw_normal_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    normal_varying: usize,
+) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        let mut normal = fragment.varyings.get_vector3(normal_varying);
+        normal.normalize();
+
+        Color::rgb(
+            0.5 * normal.x + 0.5,
+            0.5 * normal.y + 0.5,
+            0.5 * normal.z + 0.5,
+        )
+        .to_argb()
+    });
+}
+
+/// Draws a triangle colored by its interpolated texel coordinates. The
+/// fractional parts of u and v are shown in red and green, so repeating UVs
+/// show up as repeating gradients. Pixels with UVs outside of [0.0, 1.0] are
+/// tinted blue.
+pub fn draw_uv_triangle(color_buffer: &mut ColorBuffer, triangle: &Triangle) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        let uv = &fragment.uv;
+        let outside =
+            !(0.0..=1.0).contains(&uv.u) || !(0.0..=1.0).contains(&uv.v);
+
+        Color::rgb(
+            uv.u - uv.u.floor(),
+            uv.v - uv.v.floor(),
+            if outside { 1.0 } else { 0.0 },
+        )
+        .to_argb()
+    });
+}
+
+/// Replaces the colors of a color buffer with a heatmap of its overdraw
+/// counts, from blue for pixels drawn once to red for pixels drawn
+/// OVERDRAW_MAX_COUNT times. A legend of the colors is drawn in the bottom
+/// left corner, with a dot under each swatch per fragment it stands for. Does
+/// nothing if the buffer isn't counting overdraw.
+pub fn draw_overdraw_heatmap(color_buffer: &mut ColorBuffer) {
+    let overdraw = match &color_buffer.overdraw {
+        Some(overdraw) => overdraw,
+        None => return,
+    };
+
+    for (pixel, count) in color_buffer.buffer.iter_mut().zip(overdraw) {
+        *pixel = calc_overdraw_color(*count);
+    }
+
+    let top = color_buffer.height as i32 - 2 * LEGEND_SWATCH_SIZE;
+    for count in 1..=OVERDRAW_MAX_COUNT {
+        let left = LEGEND_MARGIN
+            + (count as i32 - 1) * (LEGEND_SWATCH_SIZE + LEGEND_MARGIN);
+        draw_rect(
+            color_buffer,
+            left,
+            top,
+            LEGEND_SWATCH_SIZE,
+            LEGEND_SWATCH_SIZE,
+            calc_overdraw_color(count),
+        );
+
+        for dot in 0..count as i32 {
+            draw_rect(
+                color_buffer,
+                left + 2 * dot,
+                top + LEGEND_SWATCH_SIZE + 2,
+                1,
+                2,
+                0xFFFFFFFF,
+            );
+        }
+    }
+}
+
+/// Colors an overdraw count for the heatmap
+fn calc_overdraw_color(count: u32) -> u32 {
+    match count {
+        0 => 0xFF000000,
+        count if count > OVERDRAW_MAX_COUNT => 0xFFFFFFFF,
+        count => calc_false_color(
+            (count - 1) as f32 / (OVERDRAW_MAX_COUNT - 1) as f32,
+        )
+        .to_argb(),
+    }
+}
+
+/// Draws a triangle colored by its area in screen space on a logarithmic
+/// scale, from red for triangles smaller than a pixel to blue for triangles of
+/// TRIANGLE_AREA_MAX pixels or more
+pub fn draw_area_triangle(color_buffer: &mut ColorBuffer, triangle: &Triangle) {
+    let [point_0, point_1, point_2] = &triangle.points;
+    let area = 0.5
+        * ((point_1.x - point_0.x) * (point_2.y - point_0.y)
+            - (point_2.x - point_0.x) * (point_1.y - point_0.y))
+            .abs();
+
+    let smallness = 1.0 - area.max(1.0).log2() / TRIANGLE_AREA_MAX.log2();
+    let color = calc_false_color(smallness).to_argb();
+
+    draw_shaded_triangle(color_buffer, triangle, |_| color);
+}
+
+/// Draws a legend for the triangle area view in the bottom left corner: a
+/// swatch for each power of 4 pixels of area, from 1 pixel on the left
+pub fn draw_area_legend(color_buffer: &mut ColorBuffer) {
+    let top = color_buffer.height as i32 - 2 * LEGEND_SWATCH_SIZE;
+    let swatch_count = (TRIANGLE_AREA_MAX.log2() / 2.0) as i32 + 1;
+    for swatch in 0..swatch_count {
+        let left =
+            LEGEND_MARGIN + swatch * (LEGEND_SWATCH_SIZE + LEGEND_MARGIN);
+        let smallness = 1.0 - (2 * swatch) as f32 / TRIANGLE_AREA_MAX.log2();
+        draw_rect(
+            color_buffer,
+            left,
+            top,
+            LEGEND_SWATCH_SIZE,
+            LEGEND_SWATCH_SIZE,
+            calc_false_color(smallness).to_argb(),
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    frame_stats::FrameStats,
+    material::AlphaMode,
+    render::{rasterize_triangle, ColorBuffer},
+    triangle::Triangle,
+    vector::Vector4,
+};
+
+/// Size in pixels of the tiles of the finest level of a depth pyramid
+const BASE_TILE_SIZE: usize = 4;
+
+/// One level of a depth pyramid. Each tile stores the farthest reciprocal
+/// depth of the pixels it covers.
+struct DepthPyramidLevel {
+    /// Size of the level in tiles
+    width: usize,
+    height: usize,
+    /// Size of each tile in pixels
+    tile_size: usize,
+    farthest_reciprocal_zs: Vec<f32>,
+}
+
+/// A hierarchy of progressively coarser tiles over a zbuffer for occlusion
+/// culling. Each tile knows the farthest depth drawn anywhere inside of it, so
+/// anything that is behind that depth everywhere in the tile is hidden. Each
+/// level has half the resolution of the level before it, down to a single
+/// tile.
+pub struct DepthPyramid {
+    levels: Vec<DepthPyramidLevel>,
+    /// Size of the zbuffer in pixels
+    width: usize,
+    height: usize,
+}
+
+impl DepthPyramid {
+    /// Builds a depth pyramid from a color buffer's zbuffer
+    pub fn new(color_buffer: &ColorBuffer) -> Self {
+        let width = color_buffer.width;
+        let height = color_buffer.height;
+
+        // Each base tile also takes in the pixels bordering it. Rasterizing
+        // with multisampling covers slightly different pixels than the depth
+        // prepass, so occluders are shrunk by a pixel to stay conservative.
+        let base_width = width.div_ceil(BASE_TILE_SIZE);
+        let base_height = height.div_ceil(BASE_TILE_SIZE);
+        let mut farthest_reciprocal_zs = vec![0.0; base_width * base_height];
+        for tile_y in 0..base_height {
+            for tile_x in 0..base_width {
+                let min_x = (tile_x * BASE_TILE_SIZE).saturating_sub(1);
+                let min_y = (tile_y * BASE_TILE_SIZE).saturating_sub(1);
+                let max_x = ((tile_x + 1) * BASE_TILE_SIZE + 1).min(width);
+                let max_y = ((tile_y + 1) * BASE_TILE_SIZE + 1).min(height);
+
+                let mut farthest_reciprocal_z = f32::MAX;
+                for y in min_y..max_y {
+                    for x in min_x..max_x {
+                        farthest_reciprocal_z = farthest_reciprocal_z
+                            .min(color_buffer.zbuffer[y * width + x]);
+                    }
+                }
+
+                farthest_reciprocal_zs[tile_y * base_width + tile_x] =
+                    farthest_reciprocal_z;
+            }
+        }
+
+        let mut levels = vec![DepthPyramidLevel {
+            width: base_width,
+            height: base_height,
+            tile_size: BASE_TILE_SIZE,
+            farthest_reciprocal_zs,
+        }];
+
+        // Each coarser tile is the farthest of the 2x2 tiles below it
+        while let Some(level) = levels.last() {
+            if level.width <= 1 && level.height <= 1 {
+                break;
+            }
+
+            let coarse_width = level.width.div_ceil(2);
+            let coarse_height = level.height.div_ceil(2);
+            let mut farthest_reciprocal_zs =
+                vec![0.0; coarse_width * coarse_height];
+            for tile_y in 0..coarse_height {
+                for tile_x in 0..coarse_width {
+                    let mut farthest_reciprocal_z = f32::MAX;
+                    for y in (2 * tile_y)..(2 * tile_y + 2).min(level.height) {
+                        for x in (2 * tile_x)..(2 * tile_x + 2).min(level.width)
+                        {
+                            farthest_reciprocal_z = farthest_reciprocal_z.min(
+                                level.farthest_reciprocal_zs
+                                    [y * level.width + x],
+                            );
+                        }
+                    }
+
+                    farthest_reciprocal_zs[tile_y * coarse_width + tile_x] =
+                        farthest_reciprocal_z;
+                }
+            }
+
+            let tile_size = 2 * level.tile_size;
+            levels.push(DepthPyramidLevel {
+                width: coarse_width,
+                height: coarse_height,
+                tile_size,
+                farthest_reciprocal_zs,
+            });
+        }
+
+        Self {
+            levels,
+            width,
+            height,
+        }
+    }
+
+    /// Returns true if a screen space rectangle (in pixels, inclusive) whose
+    /// nearest point has 'nearest_reciprocal_z' is behind everything drawn in
+    /// it. The rectangle is first tested against the coarsest level where it
+    /// covers at most 2x2 tiles, and tiles that don't hide it there are
+    /// tested again with the finer tiles inside of them.
+    pub fn is_rect_occluded(
+        &self,
+        min_x: i32,
+        min_y: i32,
+        max_x: i32,
+        max_y: i32,
+        nearest_reciprocal_z: f32,
+    ) -> bool {
+        // Rectangles that are off screen are left to clipping
+        if max_x < 0
+            || max_y < 0
+            || min_x >= self.width as i32
+            || min_y >= self.height as i32
+            || nearest_reciprocal_z <= 0.0
+        {
+            return false;
+        }
+
+        let rect = (
+            min_x.max(0) as usize,
+            min_y.max(0) as usize,
+            (max_x as usize).min(self.width - 1),
+            (max_y as usize).min(self.height - 1),
+        );
+        let (min_x, min_y, max_x, max_y) = rect;
+
+        let level_index = self
+            .levels
+            .iter()
+            .position(|level| {
+                max_x / level.tile_size - min_x / level.tile_size <= 1
+                    && max_y / level.tile_size - min_y / level.tile_size <= 1
+            })
+            .unwrap_or(self.levels.len() - 1);
+        let tile_size = self.levels[level_index].tile_size;
+
+        for tile_y in (min_y / tile_size)..=(max_y / tile_size) {
+            for tile_x in (min_x / tile_size)..=(max_x / tile_size) {
+                if !self.is_tile_occluded(
+                    level_index,
+                    tile_x,
+                    tile_y,
+                    rect,
+                    nearest_reciprocal_z,
+                ) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Returns true if the part of a rectangle (min_x, min_y, max_x, max_y)
+    /// inside of a tile is behind everything drawn there
+    fn is_tile_occluded(
+        &self,
+        level_index: usize,
+        tile_x: usize,
+        tile_y: usize,
+        rect: (usize, usize, usize, usize),
+        nearest_reciprocal_z: f32,
+    ) -> bool {
+        let level = &self.levels[level_index];
+        if nearest_reciprocal_z
+            < level.farthest_reciprocal_zs[tile_y * level.width + tile_x]
+        {
+            return true;
+        }
+        if level_index == 0 {
+            return false;
+        }
+
+        // Each tile is the farthest of the 2x2 tiles below it, so the finer
+        // tiles can still hide the parts of the rectangle they cover
+        let (min_x, min_y, max_x, max_y) = rect;
+        let finer_level = &self.levels[level_index - 1];
+        let finer_tile_size = finer_level.tile_size;
+        let finer_tiles_x = (2 * tile_x).max(min_x / finer_tile_size)
+            ..=(2 * tile_x + 1)
+                .min(max_x / finer_tile_size)
+                .min(finer_level.width - 1);
+        let finer_tiles_y = (2 * tile_y).max(min_y / finer_tile_size)
+            ..=(2 * tile_y + 1)
+                .min(max_y / finer_tile_size)
+                .min(finer_level.height - 1);
+
+        for finer_tile_y in finer_tiles_y {
+            for finer_tile_x in finer_tiles_x.clone() {
+                if !self.is_tile_occluded(
+                    level_index - 1,
+                    finer_tile_x,
+                    finer_tile_y,
+                    rect,
+                    nearest_reciprocal_z,
+                ) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Returns true if a screen space triangle is behind everything drawn
+    /// where it would be rasterized
+    pub fn is_triangle_occluded(&self, triangle: &Triangle) -> bool {
+        self.is_convex_hull_occluded(&triangle.points)
+    }
+
+    /// Returns true if everything inside the convex hull of screen space
+    /// points is behind everything drawn there
+    pub fn is_convex_hull_occluded(&self, points: &[Vector4]) -> bool {
+        let mut min_x = f32::MAX;
+        let mut max_x = f32::MIN;
+        let mut min_y = f32::MAX;
+        let mut max_y = f32::MIN;
+        for point in points {
+            min_x = min_x.min(point.x);
+            max_x = max_x.max(point.x);
+            min_y = min_y.min(point.y);
+            max_y = max_y.max(point.y);
+        }
+
+        // Depth is interpolated between the points, so nothing inside of the
+        // hull is nearer than its nearest point
+        let nearest_reciprocal_z =
+            points.iter().map(|point| 1.0 / point.w).fold(0.0, f32::max);
+
+        // Leave a pixel around the bounds for vertex snapping and
+        // multisampling
+        self.is_rect_occluded(
+            min_x.floor() as i32 - 1,
+            min_y.floor() as i32 - 1,
+            max_x.ceil() as i32 + 1,
+            max_y.ceil() as i32 + 1,
+            nearest_reciprocal_z,
+        )
+    }
+}
+
+/// Culls whatever is hidden behind the opaque triangles of a frame. The
+/// occluders are drawn into a zbuffer of their own as they are produced, so
+/// whole instances can be tested against the instances in front of them
+/// before they are transformed. Triangles and instances that are reported as
+/// occluded can be skipped, since every pixel of them would fail the depth
+/// test anyway.
+pub struct OcclusionCuller {
+    depth_buffer: ColorBuffer,
+    /// Built from the depth buffer when it is first needed after occluders
+    /// were drawn
+    depth_pyramid: Option<DepthPyramid>,
+}
+
+impl OcclusionCuller {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            depth_buffer: ColorBuffer::new(width, height),
+            depth_pyramid: None,
+        }
+    }
+
+    /// Removes every occluder, resizing the culler to match the buffer that
+    /// will be rendered to
+    pub fn clear(&mut self, width: usize, height: usize) {
+        if self.depth_buffer.width != width
+            || self.depth_buffer.height != height
+        {
+            self.depth_buffer = ColorBuffer::new(width, height);
+        } else {
+            self.depth_buffer.zbuffer.fill(0.0);
+        }
+        self.depth_pyramid = None;
+    }
+
+    /// Draws the depth of screen space triangles that will be rendered.
+    /// Triangles that can be transparent (alpha tested or blended) don't
+    /// occlude anything, so they are skipped.
+    pub fn draw_occluders(&mut self, triangles: &[Triangle]) {
+        let mut drawn = false;
+        for triangle in triangles {
+            if triangle.material.alpha_mode != AlphaMode::Opaque {
+                continue;
+            }
+
+            rasterize_triangle(triangle, |fragment| {
+                self.depth_buffer.set_zcell(
+                    fragment.x as usize,
+                    fragment.y as usize,
+                    fragment.reciprocal_w,
+                );
+            });
+            drawn = true;
+        }
+
+        // The pyramid is rebuilt from the new depths when it's next needed
+        if drawn {
+            self.depth_pyramid = None;
+        }
+    }
+
+    /// Returns the depth pyramid of the occluders drawn so far
+    fn depth_pyramid(&mut self) -> &DepthPyramid {
+        self.depth_pyramid
+            .get_or_insert_with(|| DepthPyramid::new(&self.depth_buffer))
+    }
+
+    /// Returns true if everything inside the convex hull of screen space
+    /// points is hidden behind the occluders
+    pub fn is_convex_hull_occluded(&mut self, points: &[Vector4]) -> bool {
+        self.depth_pyramid().is_convex_hull_occluded(points)
+    }
+
+    /// Moves the triangles that are hidden behind the occluders out of
+    /// 'triangles' and returns them, keeping the order of the rest. The
+    /// number of occluded triangles is recorded in 'stats'.
+    pub fn cull_occluded_triangles(
+        &mut self,
+        triangles: &mut Vec<Triangle>,
+        stats: &mut FrameStats,
+    ) -> Vec<Triangle> {
+        let depth_pyramid = self.depth_pyramid();
+
+        let (visible_triangles, occluded_triangles): (Vec<_>, Vec<_>) =
+            std::mem::take(triangles).into_iter().partition(|triangle| {
+                !depth_pyramid.is_triangle_occluded(triangle)
+            });
+        *triangles = visible_triangles;
+        stats.occluded_triangle_count = occluded_triangles.len();
+
+        occluded_triangles
+    }
+}
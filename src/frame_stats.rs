@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+/// Counts and timings of each stage of the pipeline for a single frame
+#[derive(Clone, Copy, Default)]
+pub struct FrameStats {
+    /// Instances whose mesh was processed
+    pub instance_count: usize,
+    /// Instances skipped by occlusion culling for having their bounding box
+    /// hidden behind opaque triangles
+    pub occluded_instance_count: usize,
+    /// Faces that were transformed
+    pub face_count: usize,
+    /// Faces discarded by backface culling
+    pub culled_face_count: usize,
+    /// Faces that were entirely outside of the view frustum
+    pub clipped_face_count: usize,
+    /// Triangles that clipping produced from the faces that weren't culled
+    pub clipped_triangle_count: usize,
+    /// Triangles skipped by occlusion culling for being hidden behind opaque
+    /// triangles
+    pub occluded_triangle_count: usize,
+    /// Triangles that were rasterized into the color buffer
+    pub rasterized_triangle_count: usize,
+    /// Fragments that were shaded while rasterizing triangles
+    pub shaded_pixel_count: usize,
+    /// Time spent drawing the shadow maps of the lights
+    pub shadow_time: Duration,
+    /// Time spent transforming, culling and lighting faces
+    pub transform_time: Duration,
+    /// Time spent clipping triangles against the view frustum
+    pub clip_time: Duration,
+    /// Time spent projecting triangles to screen space
+    pub project_time: Duration,
+    /// Time spent drawing into the color buffer, including post-processing
+    pub raster_time: Duration,
+    /// Time spent copying the color buffer to the window
+    pub present_time: Duration,
+}
+
+impl FrameStats {
+    /// Column names of the rows written by FrameStatsCsv
+    pub const CSV_HEADER: &'static str = "frame,instances,occluded_instances,faces,\
+        culled_faces,clipped_faces,clipped_triangles,occluded_triangles,rasterized_triangles,\
+        shaded_pixels,shadow_ms,transform_ms,clip_ms,project_ms,raster_ms,present_ms";
+
+    /// Formats the stats as a CSV row. Times are in milliseconds.
+    pub fn to_csv_row(self, frame_index: usize) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            frame_index,
+            self.instance_count,
+            self.occluded_instance_count,
+            self.face_count,
+            self.culled_face_count,
+            self.clipped_face_count,
+            self.clipped_triangle_count,
+            self.occluded_triangle_count,
+            self.rasterized_triangle_count,
+            self.shaded_pixel_count,
+            1000.0 * self.shadow_time.as_secs_f64(),
+            1000.0 * self.transform_time.as_secs_f64(),
+            1000.0 * self.clip_time.as_secs_f64(),
+            1000.0 * self.project_time.as_secs_f64(),
+            1000.0 * self.raster_time.as_secs_f64(),
+            1000.0 * self.present_time.as_secs_f64(),
+        )
+    }
+}
+
+/// Writes the stats of each frame to a CSV file
+pub struct FrameStatsCsv {
+    writer: BufWriter<File>,
+    frame_index: usize,
+}
+
+impl FrameStatsCsv {
+    /// Creates (or truncates) a CSV file and writes its header
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|error| format!("Unable to create {}: {}", path, error))?;
+
+        let mut csv = Self {
+            writer: BufWriter::new(file),
+            frame_index: 0,
+        };
+        writeln!(csv.writer, "{}", FrameStats::CSV_HEADER).map_err(
+            |error| format!("Unable to write frame stats: {}", error),
+        )?;
+
+        Ok(csv)
+    }
+
+    /// Appends a row for the next frame
+    pub fn write(&mut self, stats: &FrameStats) -> Result<(), String> {
+        writeln!(self.writer, "{}", stats.to_csv_row(self.frame_index))
+            .map_err(|error| {
+                format!("Unable to write frame stats: {}", error)
+            })?;
+        self.frame_index += 1;
+
+        Ok(())
+    }
+}
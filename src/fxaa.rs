@@ -0,0 +1,179 @@
+use crate::{color::Color, render::ColorBuffer};
+
+// Pixels whose local contrast is below the larger of these thresholds are
+// left alone. The relative threshold skips small changes in bright areas and
+// the absolute threshold skips noise in dark areas.
+const EDGE_THRESHOLD: f32 = 0.125;
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+/// How many pixels to search along an edge in each direction for its ends
+const EDGE_SEARCH_STEPS: i32 = 12;
+/// How strongly details that are too small to be part of a longer edge (such
+/// as single pixels) are smoothed, in [0.0, 1.0]
+const SUBPIXEL_QUALITY: f32 = 0.75;
+
+/// Smooths aliased edges in the final colors of a color buffer, in the style
+/// of FXAA. Edges are found from the contrast in luminance between each pixel
+/// and its neighbors. The ends of each edge are searched for to estimate
+/// where the edge crosses the pixel, and the pixel is blended with its
+/// neighbor across the edge by that amount. Since it only looks at colors, it
+/// works on anything that was drawn, including lines.
+pub fn apply_fxaa(color_buffer: &mut ColorBuffer) {
+    let width = color_buffer.width as i32;
+    let height = color_buffer.height as i32;
+
+    // Read from a copy so that blended pixels don't affect their neighbors
+    let source = color_buffer.buffer.clone();
+    let lumas: Vec<f32> =
+        source.iter().map(|color| calc_luma(*color)).collect();
+
+    let get_index = |x: i32, y: i32| -> usize {
+        let x = x.clamp(0, width - 1);
+        let y = y.clamp(0, height - 1);
+        (y * width + x) as usize
+    };
+    let get_luma = |x: i32, y: i32| lumas[get_index(x, y)];
+
+    for y in 0..height {
+        for x in 0..width {
+            let luma_center = get_luma(x, y);
+            let luma_north = get_luma(x, y - 1);
+            let luma_south = get_luma(x, y + 1);
+            let luma_west = get_luma(x - 1, y);
+            let luma_east = get_luma(x + 1, y);
+
+            let luma_min = luma_center
+                .min(luma_north)
+                .min(luma_south)
+                .min(luma_west)
+                .min(luma_east);
+            let luma_max = luma_center
+                .max(luma_north)
+                .max(luma_south)
+                .max(luma_west)
+                .max(luma_east);
+            let luma_range = luma_max - luma_min;
+            if luma_range < EDGE_THRESHOLD_MIN.max(luma_max * EDGE_THRESHOLD) {
+                continue;
+            }
+
+            let luma_north_west = get_luma(x - 1, y - 1);
+            let luma_north_east = get_luma(x + 1, y - 1);
+            let luma_south_west = get_luma(x - 1, y + 1);
+            let luma_south_east = get_luma(x + 1, y + 1);
+
+            // The edge runs along the axis where the luminance changes the
+            // least. Compare how much it changes across rows and columns.
+            let change_across_rows = (luma_north_west - 2.0 * luma_west
+                + luma_south_west)
+                .abs()
+                + 2.0 * (luma_north - 2.0 * luma_center + luma_south).abs()
+                + (luma_north_east - 2.0 * luma_east + luma_south_east).abs();
+            let change_across_columns = (luma_north_west - 2.0 * luma_north
+                + luma_north_east)
+                .abs()
+                + 2.0 * (luma_west - 2.0 * luma_center + luma_east).abs()
+                + (luma_south_west - 2.0 * luma_south + luma_south_east).abs();
+            let is_horizontal = change_across_rows >= change_across_columns;
+
+            // The edge lies between this pixel and whichever neighbor across
+            // the edge has the steeper gradient
+            let (luma_negative, luma_positive) = if is_horizontal {
+                (luma_north, luma_south)
+            } else {
+                (luma_west, luma_east)
+            };
+            let gradient_negative = (luma_negative - luma_center).abs();
+            let gradient_positive = (luma_positive - luma_center).abs();
+            let (step, luma_across, gradient) =
+                if gradient_negative >= gradient_positive {
+                    (-1, luma_negative, gradient_negative)
+                } else {
+                    (1, luma_positive, gradient_positive)
+                };
+
+            // Offsets of one pixel along the edge and across it
+            let (along_x, along_y, across_x, across_y) = if is_horizontal {
+                (1, 0, 0, step)
+            } else {
+                (0, 1, step, 0)
+            };
+
+            // Luminance on the edge itself, halfway between the pixels on
+            // either side of it
+            let get_edge_luma = |distance: i32| {
+                let edge_x = x + along_x * distance;
+                let edge_y = y + along_y * distance;
+                0.5 * (get_luma(edge_x, edge_y)
+                    + get_luma(edge_x + across_x, edge_y + across_y))
+            };
+            let luma_edge = 0.5 * (luma_center + luma_across);
+            let end_threshold = 0.25 * gradient;
+
+            // Walk along the edge until the luminance on it changes enough to
+            // mean the edge has ended. Returns the distance to the end and
+            // the change in luminance there.
+            let find_edge_end = |direction: i32| {
+                let mut distance = 1;
+                let mut delta = get_edge_luma(direction) - luma_edge;
+                while delta.abs() < end_threshold
+                    && distance < EDGE_SEARCH_STEPS
+                {
+                    distance += 1;
+                    delta = get_edge_luma(direction * distance) - luma_edge;
+                }
+
+                (distance, delta)
+            };
+            let (distance_negative, delta_negative) = find_edge_end(-1);
+            let (distance_positive, delta_positive) = find_edge_end(1);
+
+            // The edge crosses the pixel farther from the center the closer
+            // the pixel is to an end of the edge. Only blend if the nearer end
+            // bends towards this pixel's side of the edge.
+            let (distance, delta) = if distance_negative < distance_positive {
+                (distance_negative, delta_negative)
+            } else {
+                (distance_positive, delta_positive)
+            };
+            let edge_length = (distance_negative + distance_positive) as f32;
+            let edge_offset = if (delta < 0.0) != (luma_center < luma_edge) {
+                0.5 - distance as f32 / edge_length
+            } else {
+                0.0
+            };
+
+            // Smooth small details by how much the pixel stands out from the
+            // average of its neighbors
+            let subpixel_offset = {
+                let luma_average = (2.0
+                    * (luma_north + luma_south + luma_west + luma_east)
+                    + luma_north_west
+                    + luma_north_east
+                    + luma_south_west
+                    + luma_south_east)
+                    / 12.0;
+                let contrast = ((luma_average - luma_center).abs()
+                    / luma_range)
+                    .clamp(0.0, 1.0);
+                let contrast = (3.0 - 2.0 * contrast) * contrast * contrast;
+                contrast * contrast * SUBPIXEL_QUALITY
+            };
+
+            let offset = edge_offset.max(subpixel_offset);
+            let index = get_index(x, y);
+            let color = Color::from_argb(source[index]);
+            let color_across =
+                Color::from_argb(source[get_index(x + across_x, y + across_y)]);
+
+            color_buffer.buffer[index] = (&((1.0 - offset) * &color)
+                + &(offset * &color_across))
+                .to_argb();
+        }
+    }
+}
+
+/// Calculates the perceived brightness of an ARGB color in [0.0, 1.0]
+fn calc_luma(color: u32) -> f32 {
+    let color = Color::from_argb(color);
+    0.299 * color.r + 0.587 * color.g + 0.114 * color.b
+}
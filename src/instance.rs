@@ -1,9 +1,46 @@
-use crate::vector::Vector4;
+use crate::{
+    asset_manager::{MeshHandle, TextureHandle},
+    matrix::Matrix4,
+    vector::Vector4,
+};
 
 pub struct Instance {
     pub orientation: Vector4,
     pub translation: Vector4,
     pub scale: f32,
-    pub mesh_handle: usize,
-    pub texture_handle: usize,
+    pub mesh_handle: MeshHandle,
+    pub texture_handle: TextureHandle,
+}
+
+impl Instance {
+    /// Returns the matrix that transforms the instance's mesh from model
+    /// space to world space
+    pub fn world_matrix(&self) -> Matrix4 {
+        let world_matrix = Matrix4::identity();
+
+        let world_matrix = Matrix4::mult_mat4(
+            &Matrix4::scale(self.scale, self.scale, self.scale),
+            &world_matrix,
+        );
+        let world_matrix = Matrix4::mult_mat4(
+            &Matrix4::rotate_around_x(self.orientation.x),
+            &world_matrix,
+        );
+        let world_matrix = Matrix4::mult_mat4(
+            &Matrix4::rotate_around_y(self.orientation.y),
+            &world_matrix,
+        );
+        let world_matrix = Matrix4::mult_mat4(
+            &Matrix4::rotate_around_z(self.orientation.z),
+            &world_matrix,
+        );
+
+        let translation_matrix = Matrix4::translate(
+            self.translation.x,
+            self.translation.y,
+            self.translation.z,
+        );
+
+        Matrix4::mult_mat4(&translation_matrix, &world_matrix)
+    }
 }
@@ -1,22 +1,326 @@
-use crate::vector::Vector3;
+use crate::{
+    color::Color,
+    matrix::Matrix4,
+    shadow::ShadowMap,
+    vector::{Vector3, Vector4},
+};
 
-pub struct LightSource {
-    pub direction: Vector3,
+/// Distance falloff for point and spot lights. The light's intensity at a
+/// distance d is divided by (constant + linear * d + quadratic * d^2).
+#[derive(Clone, Copy)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
 }
 
-impl LightSource {
-    pub fn new(direction: Vector3) -> Self {
-        let mut direction = direction;
-        direction.normalize();
-        Self { direction }
+impl Attenuation {
+    pub fn calc_factor(&self, distance: f32) -> f32 {
+        1.0 / (self.constant
+            + self.linear * distance
+            + self.quadratic * distance * distance)
     }
 }
 
-pub fn apply_intensity(color: u32, intensity: f32) -> u32 {
-    let a = color & 0xFF000000;
-    let r = ((color & 0x00FF0000) as f32 * intensity) as u32;
-    let g = ((color & 0x0000FF00) as f32 * intensity) as u32;
-    let b = ((color & 0x000000FF) as f32 * intensity) as u32;
+#[derive(Clone, Copy)]
+pub enum LightKind {
+    /// Light that reaches every surface regardless of position or orientation
+    Ambient,
+    /// Light that comes from infinitely far away, such as the sun
+    Directional { direction: Vector3 },
+    /// Light that is emitted in every direction from a single position
+    Point {
+        position: Vector3,
+        attenuation: Attenuation,
+    },
+    /// Light that is emitted from a single position in a cone. Surfaces within
+    /// inner_angle of the direction are fully lit and the light fades out
+    /// between inner_angle and outer_angle. If the angles are equal, the cone
+    /// has a hard edge. Angles are in radians.
+    Spot {
+        position: Vector3,
+        direction: Vector3,
+        inner_angle: f32,
+        outer_angle: f32,
+        attenuation: Attenuation,
+    },
+}
+
+/// A light in the scene
+#[derive(Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Color,
+    pub intensity: f32,
+    pub enabled: bool,
+}
+
+impl Light {
+    pub fn ambient(color: Color, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Ambient,
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+
+    pub fn directional(
+        direction: Vector3,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Directional {
+                direction: Vector3::calc_normalized_vector(&direction),
+            },
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+
+    pub fn point(
+        position: Vector3,
+        attenuation: Attenuation,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Point {
+                position,
+                attenuation,
+            },
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+
+    pub fn spot(
+        position: Vector3,
+        direction: Vector3,
+        inner_angle: f32,
+        outer_angle: f32,
+        attenuation: Attenuation,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction: Vector3::calc_normalized_vector(&direction),
+                inner_angle,
+                outer_angle,
+                attenuation,
+            },
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            LightKind::Ambient => "ambient",
+            LightKind::Directional { .. } => "directional",
+            LightKind::Point { .. } => "point",
+            LightKind::Spot { .. } => "spot",
+        }
+    }
+
+    /// Returns a copy of the light with its position and direction transformed
+    /// by the view matrix. Lights are defined in world space, but shading
+    /// happens in view space.
+    pub fn to_view_space(self, view_matrix: &Matrix4) -> Self {
+        let transform_position = |position: &Vector3| {
+            Vector3::from_vector4(&Matrix4::mult_vector(
+                view_matrix,
+                &Vector4::from_vector3(position),
+            ))
+        };
+        // Directions are not affected by translation (w = 0)
+        let transform_direction = |direction: &Vector3| {
+            let direction = Matrix4::mult_vector(
+                view_matrix,
+                &Vector4 {
+                    w: 0.0,
+                    ..Vector4::from_vector3(direction)
+                },
+            );
+            Vector3::calc_normalized_vector(&Vector3::from_vector4(&direction))
+        };
+
+        let kind = match self.kind {
+            LightKind::Ambient => LightKind::Ambient,
+            LightKind::Directional { direction } => LightKind::Directional {
+                direction: transform_direction(&direction),
+            },
+            LightKind::Point {
+                position,
+                attenuation,
+            } => LightKind::Point {
+                position: transform_position(&position),
+                attenuation,
+            },
+            LightKind::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => LightKind::Spot {
+                position: transform_position(&position),
+                direction: transform_direction(&direction),
+                inner_angle,
+                outer_angle,
+                attenuation,
+            },
+        };
+
+        Self { kind, ..self }
+    }
+
+    /// Finds the normalized direction from a surface point to the light and
+    /// the amount of light that reaches the point. Returns None for ambient
+    /// lights since they have no direction.
+    fn calc_incoming(&self, position: &Vector3) -> Option<(Vector3, f32)> {
+        match self.kind {
+            LightKind::Ambient => None,
+            LightKind::Directional { direction } => {
+                Some((-1.0 * &direction, self.intensity))
+            }
+            LightKind::Point {
+                position: light_position,
+                attenuation,
+            } => {
+                let to_light = &light_position - position;
+                let distance = to_light.magnitude();
+                Some((
+                    Vector3::calc_normalized_vector(&to_light),
+                    self.intensity * attenuation.calc_factor(distance),
+                ))
+            }
+            LightKind::Spot {
+                position: light_position,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => {
+                let to_light = &light_position - position;
+                let distance = to_light.magnitude();
+                let to_light = Vector3::calc_normalized_vector(&to_light);
+
+                // Fade linearly (in cosine space) between the inner and outer
+                // cones. Without a gap between the cones (or with the inner
+                // cone wider than the outer one) the edge is a hard cutoff.
+                let cos_angle =
+                    Vector3::dot_product(&(-1.0 * &to_light), &direction);
+                let cos_inner = inner_angle.cos();
+                let cos_outer = outer_angle.cos();
+                let cone_factor = if cos_inner - cos_outer > f32::EPSILON {
+                    ((cos_angle - cos_outer) / (cos_inner - cos_outer))
+                        .clamp(0.0, 1.0)
+                } else if cos_angle >= cos_outer {
+                    1.0
+                } else {
+                    0.0
+                };
+
+                Some((
+                    to_light,
+                    self.intensity
+                        * cone_factor
+                        * attenuation.calc_factor(distance),
+                ))
+            }
+        }
+    }
+}
+
+/// Calculates the diffuse and specular (Blinn-Phong) light reaching a point on
+/// a surface from every enabled light. The normal and position should be in
+/// view space, where the viewer is at the origin. The world position is used
+/// to look up shadows; shadow_maps[i] shadows lights[i] and may be empty. The
+/// results may exceed 1.0 when several lights overlap.
+pub fn calc_lighting(
+    lights: &[Light],
+    shadow_maps: &[ShadowMap],
+    normal: &Vector3,
+    position: &Vector3,
+    world_position: &Vector3,
+    shininess: f32,
+) -> (Color, Color) {
+    let mut diffuse = Color::default();
+    let mut specular = Color::default();
+
+    let to_viewer = Vector3::calc_normalized_vector(&(-1.0 * position));
+
+    for (light_index, light) in lights.iter().enumerate() {
+        if !light.enabled {
+            continue;
+        }
+
+        let (to_light, intensity) = match light.calc_incoming(position) {
+            Some(incoming) => incoming,
+            None => {
+                diffuse = &diffuse + &(light.intensity * &light.color);
+                continue;
+            }
+        };
+
+        /*
+         * If the dot product is positive, then the normal and the to-light vector are pointing in the same direction,
+         * which means that there should be light
+         *
+         * If the dot product is 0, then the normal and the light are orthogonal, and there should be no light.
+         *
+         * If the dot product is negative, then the surface is facing away from the light, and there should be no
+         * light.
+         *
+         * Note that if both vectors are normalized, then the dot product shall be in the range [-1.0, 1.0]
+         */
+        let dot_product = Vector3::dot_product(normal, &to_light);
+        if dot_product <= 0.0 || intensity <= 0.0 {
+            continue;
+        }
+
+        let intensity = match shadow_maps.get(light_index) {
+            Some(shadow_map) => {
+                intensity * shadow_map.calc_light_factor(world_position)
+            }
+            None => intensity,
+        };
+
+        diffuse = &diffuse + &((intensity * dot_product) * &light.color);
+
+        // The halfway vector lies between the to-light and to-viewer vectors.
+        // The closer it is to the normal, the stronger the highlight.
+        let halfway =
+            Vector3::calc_normalized_vector(&(&to_light + &to_viewer));
+        let highlight = Vector3::dot_product(normal, &halfway)
+            .max(0.0)
+            .powf(shininess);
+
+        specular = &specular + &((intensity * highlight) * &light.color);
+    }
+
+    // Alpha is accumulated along with the color channels, but light is
+    // always opaque
+    (diffuse.with_alpha(1.0), specular.with_alpha(1.0))
+}
 
-    a | (r & 0x00FF0000) | (g & 0x0000FF00) | (b & 0x000000FF)
+/// Applies the light model to a surface. Each channel of the surface color is
+/// multiplied by the matching channel of the diffuse light, then the specular
+/// highlight (tinted by the material's specular color) is added. The alpha of
+/// the surface is kept.
+pub fn apply_lighting(
+    surface_color: &Color,
+    diffuse: &Color,
+    specular_color: &Color,
+    specular: &Color,
+) -> Color {
+    (&(surface_color * diffuse) + &(specular_color * specular))
+        .with_alpha(surface_color.a)
 }
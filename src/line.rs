@@ -0,0 +1,507 @@
+use crate::{
+    color::Color,
+    render::{composite_over, ColorBuffer},
+    vector::{Vector2, Vector4},
+};
+
+// Cohen–Sutherland outcodes: which sides of the clip rectangle a point is
+// outside of
+const OUTCODE_LEFT: u8 = 1;
+const OUTCODE_RIGHT: u8 = 2;
+const OUTCODE_TOP: u8 = 4;
+const OUTCODE_BOTTOM: u8 = 8;
+
+/// Fraction that a line's reciprocal depth is moved towards the camera
+/// before it is depth tested (a polygon offset). Edges lie exactly on the
+/// faces they belong to, so without it they would fight with those faces.
+const HIDDEN_LINE_DEPTH_OFFSET: f32 = 0.02;
+/// Length in pixels of each dash and each gap of a dashed hidden line
+const HIDDEN_LINE_DASH_LENGTH: f32 = 4.0;
+/// Brightness of a dimmed hidden line relative to its color
+const HIDDEN_LINE_DIMMING: f32 = 0.3;
+
+/// How a line is rasterized
+#[derive(Clone, Copy)]
+pub struct LineStyle {
+    /// Width of the line in pixels
+    pub thickness: u32,
+    /// Whether the edges of the line are smoothed with partial coverage
+    /// (Xiaolin Wu's algorithm) rather than stepped (Bresenham's algorithm)
+    pub anti_aliased: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            thickness: 1,
+            anti_aliased: false,
+        }
+    }
+}
+
+/// How the parts of a depth tested line that are behind the zbuffer are drawn
+#[derive(Clone, Copy, PartialEq)]
+pub enum HiddenLineMode {
+    Removed,
+    Dashed,
+    Dimmed,
+}
+
+impl HiddenLineMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HiddenLineMode::Removed => "removed",
+            HiddenLineMode::Dashed => "dashed",
+            HiddenLineMode::Dimmed => "dimmed",
+        }
+    }
+}
+
+/// Draws a line between two points in screen space. The line is clipped to
+/// the color buffer first, so only the visible part of it is walked.
+pub fn draw_styled_line(
+    color_buffer: &mut ColorBuffer,
+    start: &Vector2,
+    end: &Vector2,
+    color: u32,
+    style: &LineStyle,
+) {
+    let size = (color_buffer.width, color_buffer.height);
+    walk_line(
+        size,
+        (start.x, start.y),
+        (end.x, end.y),
+        style,
+        |x, y, coverage| write_line_pixel(color_buffer, x, y, color, coverage),
+    );
+}
+
+/// Draws a line between two projected points, depth testing it against the
+/// zbuffer. The points' w is their depth from the camera. Hidden parts of the
+/// line are drawn according to 'hidden_line_mode'. The zbuffer isn't updated.
+pub fn draw_depth_tested_line(
+    color_buffer: &mut ColorBuffer,
+    start: &Vector4,
+    end: &Vector4,
+    color: u32,
+    style: &LineStyle,
+    hidden_line_mode: HiddenLineMode,
+) {
+    // Reciprocal depth is linear in screen space, so it can be interpolated
+    // directly along the line
+    let start_reciprocal_w = 1.0 / start.w;
+    let end_reciprocal_w = 1.0 / end.w;
+
+    let delta_x = end.x - start.x;
+    let delta_y = end.y - start.y;
+    let length_squared = delta_x * delta_x + delta_y * delta_y;
+    let length = length_squared.sqrt();
+
+    let dimmed_color = {
+        let color = Color::from_argb(color);
+        (HIDDEN_LINE_DIMMING * &color).with_alpha(color.a).to_argb()
+    };
+
+    let size = (color_buffer.width, color_buffer.height);
+    walk_line(
+        size,
+        (start.x, start.y),
+        (end.x, end.y),
+        style,
+        |x, y, coverage| {
+            if x < 0
+                || y < 0
+                || x as usize >= color_buffer.width
+                || y as usize >= color_buffer.height
+            {
+                return;
+            }
+
+            // Parameter of the pixel along the whole line, which may have been
+            // clipped before it was walked
+            let t = if length_squared > 0.0 {
+                (((x as f32 - start.x) * delta_x
+                    + (y as f32 - start.y) * delta_y)
+                    / length_squared)
+                    .clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let reciprocal_z = start_reciprocal_w
+                + t * (end_reciprocal_w - start_reciprocal_w);
+
+            let index = y as usize * color_buffer.width + x as usize;
+            if reciprocal_z * (1.0 + HIDDEN_LINE_DEPTH_OFFSET)
+                >= color_buffer.zbuffer[index]
+            {
+                write_line_pixel(color_buffer, x, y, color, coverage);
+                return;
+            }
+
+            match hidden_line_mode {
+                HiddenLineMode::Removed => {}
+                HiddenLineMode::Dashed => {
+                    let dash = (t * length / HIDDEN_LINE_DASH_LENGTH) as i32;
+                    if dash % 2 == 0 {
+                        write_line_pixel(color_buffer, x, y, color, coverage);
+                    }
+                }
+                HiddenLineMode::Dimmed => {
+                    write_line_pixel(color_buffer, x, y, dimmed_color, coverage)
+                }
+            }
+        },
+    );
+}
+
+/// Clips a line to a buffer of 'size' and calls 'plot' with each pixel of
+/// the line and how much of the pixel the line covers
+fn walk_line<F>(
+    size: (usize, usize),
+    start: (f32, f32),
+    end: (f32, f32),
+    style: &LineStyle,
+    mut plot: F,
+) where
+    F: FnMut(i32, i32, f32),
+{
+    // Leave enough room around the buffer for the width of the line, so that
+    // lines running just outside of it still draw their visible edge
+    let margin = style.thickness as f32 + 1.0;
+    let clipped = clip_line(
+        start,
+        end,
+        (-margin, -margin),
+        (size.0 as f32 - 1.0 + margin, size.1 as f32 - 1.0 + margin),
+    );
+
+    let (start, end) = match clipped {
+        Some(clipped) => clipped,
+        None => return,
+    };
+
+    if style.anti_aliased {
+        walk_wu_line(start, end, style.thickness, &mut plot);
+    } else {
+        walk_bresenham_line(
+            (start.0.round() as i32, start.1.round() as i32),
+            (end.0.round() as i32, end.1.round() as i32),
+            style.thickness,
+            &mut plot,
+        );
+    }
+}
+
+/// Walks a line with Bresenham's algorithm. Thick lines are a run of pixels
+/// across the line's major axis at each step.
+fn walk_bresenham_line<F>(
+    (x_1, y_1): (i32, i32),
+    (x_2, y_2): (i32, i32),
+    thickness: u32,
+    plot: &mut F,
+) where
+    F: FnMut(i32, i32, f32),
+{
+    let delta_x = (x_2 - x_1).abs();
+    let delta_y = -(y_2 - y_1).abs();
+    let step_x = if x_1 < x_2 { 1 } else { -1 };
+    let step_y = if y_1 < y_2 { 1 } else { -1 };
+    let x_major = delta_x >= -delta_y;
+
+    let thickness = thickness.max(1) as i32;
+    let first_offset = -(thickness - 1) / 2;
+
+    let mut error = delta_x + delta_y;
+    let (mut x, mut y) = (x_1, y_1);
+    loop {
+        for offset in first_offset..(first_offset + thickness) {
+            if x_major {
+                plot(x, y + offset, 1.0);
+            } else {
+                plot(x + offset, y, 1.0);
+            }
+        }
+
+        if x == x_2 && y == y_2 {
+            break;
+        }
+
+        let doubled_error = 2 * error;
+        if doubled_error >= delta_y {
+            error += delta_y;
+            x += step_x;
+        }
+        if doubled_error <= delta_x {
+            error += delta_x;
+            y += step_y;
+        }
+    }
+}
+
+/// Walks a line with Xiaolin Wu's algorithm. At each step along the major
+/// axis, each pixel across the line is covered by the fraction of it that is
+/// inside of the line.
+fn walk_wu_line<F>(
+    start: (f32, f32),
+    end: (f32, f32),
+    thickness: u32,
+    plot: &mut F,
+) where
+    F: FnMut(i32, i32, f32),
+{
+    // Walk along x. Steep lines are walked with x and y swapped.
+    let steep = (end.1 - start.1).abs() > (end.0 - start.0).abs();
+    let (mut start, mut end) = if steep {
+        ((start.1, start.0), (end.1, end.0))
+    } else {
+        (start, end)
+    };
+    if start.0 > end.0 {
+        std::mem::swap(&mut start, &mut end);
+    }
+
+    let delta_x = end.0 - start.0;
+    let gradient = if delta_x == 0.0 {
+        0.0
+    } else {
+        (end.1 - start.1) / delta_x
+    };
+
+    // The extent of the line across each column, which is wider than its
+    // thickness when the line is diagonal
+    let span = thickness.max(1) as f32 * (1.0 + gradient * gradient).sqrt();
+
+    for major in (start.0.round() as i32)..=(end.0.round() as i32) {
+        // Fade out the columns at the ends of the line by how much of them
+        // the line reaches into
+        let major_coverage = ((major as f32 + 0.5).min(end.0 + 0.5)
+            - (major as f32 - 0.5).max(start.0 - 0.5))
+        .clamp(0.0, 1.0);
+
+        let center = start.1 + gradient * (major as f32 - start.0);
+        let top = center - 0.5 * span;
+        let bottom = center + 0.5 * span;
+
+        for minor in (top.round() as i32)..=(bottom.round() as i32) {
+            let minor_coverage = ((minor as f32 + 0.5).min(bottom)
+                - (minor as f32 - 0.5).max(top))
+            .clamp(0.0, 1.0);
+
+            let coverage = major_coverage * minor_coverage;
+            if coverage <= 0.0 {
+                continue;
+            }
+
+            if steep {
+                plot(minor, major, coverage);
+            } else {
+                plot(major, minor, coverage);
+            }
+        }
+    }
+}
+
+/// Clips the line segment between two points to a rectangle with the
+/// Cohen–Sutherland algorithm. Returns None if no part of the line is inside
+/// of the rectangle.
+fn clip_line(
+    mut start: (f32, f32),
+    mut end: (f32, f32),
+    min: (f32, f32),
+    max: (f32, f32),
+) -> Option<((f32, f32), (f32, f32))> {
+    let calc_outcode = |(x, y): (f32, f32)| {
+        let mut outcode = 0;
+        if x < min.0 {
+            outcode |= OUTCODE_LEFT;
+        } else if x > max.0 {
+            outcode |= OUTCODE_RIGHT;
+        }
+        if y < min.1 {
+            outcode |= OUTCODE_TOP;
+        } else if y > max.1 {
+            outcode |= OUTCODE_BOTTOM;
+        }
+
+        outcode
+    };
+
+    let mut start_outcode = calc_outcode(start);
+    let mut end_outcode = calc_outcode(end);
+    loop {
+        if start_outcode | end_outcode == 0 {
+            // Entirely inside
+            return Some((start, end));
+        }
+        if start_outcode & end_outcode != 0 {
+            // Entirely on the outer side of one edge
+            return None;
+        }
+
+        // Move a point that is outside onto the edge it is outside of. The
+        // other point is on the inner side of that edge, so the line isn't
+        // parallel to it.
+        let outcode = if start_outcode != 0 {
+            start_outcode
+        } else {
+            end_outcode
+        };
+        let (delta_x, delta_y) = (end.0 - start.0, end.1 - start.1);
+        let point = if outcode & OUTCODE_TOP != 0 {
+            (start.0 + delta_x * (min.1 - start.1) / delta_y, min.1)
+        } else if outcode & OUTCODE_BOTTOM != 0 {
+            (start.0 + delta_x * (max.1 - start.1) / delta_y, max.1)
+        } else if outcode & OUTCODE_LEFT != 0 {
+            (min.0, start.1 + delta_y * (min.0 - start.0) / delta_x)
+        } else {
+            (max.0, start.1 + delta_y * (max.0 - start.0) / delta_x)
+        };
+
+        if outcode == start_outcode {
+            start = point;
+            start_outcode = calc_outcode(start);
+        } else {
+            end = point;
+            end_outcode = calc_outcode(end);
+        }
+    }
+}
+
+/// Draws a pixel of a line if it is inside of the color buffer. Partially
+/// covered pixels are blended over by the fraction that is covered.
+fn write_line_pixel(
+    color_buffer: &mut ColorBuffer,
+    x: i32,
+    y: i32,
+    color: u32,
+    coverage: f32,
+) {
+    if x < 0
+        || y < 0
+        || x as usize >= color_buffer.width
+        || y as usize >= color_buffer.height
+    {
+        return;
+    }
+
+    color_buffer.count_fragment(x as usize, y as usize);
+
+    let index = y as usize * color_buffer.width + x as usize;
+    if coverage >= 1.0 {
+        color_buffer.buffer[index] = color;
+        return;
+    }
+
+    let source = Color::from_argb(color);
+    let source = source.with_alpha(source.a * coverage).to_argb();
+    color_buffer.buffer[index] =
+        composite_over(source, color_buffer.buffer[index]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: (f32, f32) = (0.0, 0.0);
+    const MAX: (f32, f32) = (9.0, 9.0);
+
+    #[test]
+    fn clip_line_rejects_lines_fully_outside() {
+        let lines = [
+            ((-5.0, 2.0), (-1.0, 8.0)),
+            ((12.0, 2.0), (10.0, 8.0)),
+            ((2.0, -5.0), (8.0, -1.0)),
+            ((2.0, 10.0), (8.0, 14.0)),
+            // Outside of different edges, but passing by the corners
+            ((-5.0, 2.0), (2.0, -5.0)),
+            ((7.0, 12.0), (12.0, 7.0)),
+        ];
+
+        for (start, end) in lines {
+            assert!(
+                clip_line(start, end, MIN, MAX).is_none(),
+                "{:?} to {:?} should be rejected",
+                start,
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn clip_line_crosses_corners() {
+        // A diagonal through two opposite corners
+        assert_eq!(
+            clip_line((-5.0, -5.0), (15.0, 15.0), MIN, MAX),
+            Some(((0.0, 0.0), (9.0, 9.0)))
+        );
+        assert_eq!(
+            clip_line((15.0, -6.0), (-5.0, 14.0), MIN, MAX),
+            Some(((9.0, 0.0), (0.0, 9.0)))
+        );
+
+        // A line that only touches a corner
+        assert_eq!(
+            clip_line((-1.0, 1.0), (1.0, -1.0), MIN, MAX),
+            Some(((0.0, 0.0), (0.0, 0.0)))
+        );
+    }
+
+    #[test]
+    fn clip_line_moves_negative_starts_onto_the_edges() {
+        assert_eq!(
+            clip_line((-4.0, -2.0), (4.0, 2.0), MIN, MAX),
+            Some(((0.0, 0.0), (4.0, 2.0)))
+        );
+        assert_eq!(
+            clip_line((-3.0, 5.0), (6.0, 5.0), MIN, MAX),
+            Some(((0.0, 5.0), (6.0, 5.0)))
+        );
+
+        // Lines inside of the rectangle are kept as they are
+        assert_eq!(
+            clip_line((2.0, 7.0), (6.0, 1.0), MIN, MAX),
+            Some(((2.0, 7.0), (6.0, 1.0)))
+        );
+    }
+
+    #[test]
+    fn drawn_lines_include_their_first_visible_pixel() {
+        const COLOR: u32 = 0xFFFFFFFF;
+
+        for anti_aliased in [false, true] {
+            let style = LineStyle {
+                thickness: 1,
+                anti_aliased,
+            };
+
+            // Starting inside of the buffer
+            let mut color_buffer = ColorBuffer::new(10, 10);
+            let start = Vector2 { x: 2.0, y: 7.0 };
+            let end = Vector2 { x: 6.0, y: 7.0 };
+            draw_styled_line(&mut color_buffer, &start, &end, COLOR, &style);
+            let row = &color_buffer.buffer[70..80];
+            assert!(
+                row[2..=6].iter().all(|&pixel| pixel == COLOR)
+                    && row[1] == 0
+                    && row[7] == 0,
+                "anti-aliased: {}, row: {:08X?}",
+                anti_aliased,
+                row
+            );
+
+            // Starting at negative coordinates, the line is drawn from the
+            // edge of the buffer
+            let mut color_buffer = ColorBuffer::new(10, 10);
+            let start = Vector2 { x: -4.0, y: 3.0 };
+            let end = Vector2 { x: 5.0, y: 3.0 };
+            draw_styled_line(&mut color_buffer, &start, &end, COLOR, &style);
+            let row = &color_buffer.buffer[30..40];
+            assert!(
+                row[0..=5].iter().all(|&pixel| pixel == COLOR) && row[6] == 0,
+                "anti-aliased: {}, row: {:08X?}",
+                anti_aliased,
+                row
+            );
+        }
+    }
+}
@@ -1,15 +1,29 @@
 extern crate sdl3;
 
+mod asset_manager;
+mod bmp;
 mod camera;
 mod clipping;
+mod color;
+mod debug_view;
+mod depth_pyramid;
+mod font;
+mod frame_stats;
+mod fxaa;
 mod instance;
 mod light_source;
+mod line;
+mod material;
 mod matrix;
 mod mesh;
+mod pipeline;
 mod point;
+mod ppm;
 mod projection;
 mod render;
+mod shadow;
 mod texture;
+mod tga;
 mod triangle;
 mod vector;
 
@@ -19,37 +33,60 @@ use std::{
     time::{Duration, Instant},
 };
 
-use light_source::LightSource;
+use asset_manager::{AssetManager, MeshHandle, TextureHandle};
+use debug_view::{
+    draw_area_legend, draw_area_triangle, draw_depth_buffer,
+    draw_normal_triangle, draw_overdraw_heatmap, draw_uv_triangle,
+};
+use depth_pyramid::OcclusionCuller;
+use font::measure_text;
+use frame_stats::{FrameStats, FrameStatsCsv};
+use fxaa::apply_fxaa;
+use light_source::{Attenuation, Light};
+use line::{draw_styled_line, HiddenLineMode, LineStyle};
+use material::{AlphaMode, DEFAULT_ALPHA_CUTOFF};
 use matrix::Matrix4;
-use mesh::{load_obj_mesh, load_test_mesh};
+use mesh::load_test_mesh;
 use render::{
-    draw_filled_triangle, draw_textured_triangle, draw_triangle,
-    draw_triangle_vertices, ColorBuffer,
+    blend_rect, draw_depth_triangle, draw_filled_triangle,
+    draw_gouraud_textured_triangle, draw_gouraud_triangle,
+    draw_phong_textured_triangle, draw_phong_triangle, draw_textured_triangle,
+    draw_triangle, draw_triangle_vertices, sort_triangles_for_blending,
+    ColorBuffer, NORMAL_VARYING, WORLD_NORMAL_VARYING,
 };
 use sdl3::{
     event::Event,
     keyboard::Keycode,
-    pixels::{Color, PixelFormat},
+    pixels::{Color as SdlColor, PixelFormat},
     sys::{
         pixels::SDL_PIXELFORMAT_ARGB8888, render::SDL_TEXTUREACCESS_STREAMING,
     },
 };
-use texture::{load_png_texture, load_test_texture};
+use texture::{load_test_texture, FilterMode, MipmapMode, Sampler};
 use triangle::Triangle;
-use vector::{calc_cross_product, Vector3, Vector4};
+use vector::{calc_cross_product, Vector2, Vector3, Vector4};
 
 use crate::{
     camera::Camera,
-    clipping::{clip_triangle, FrustumPlanes},
+    clipping::FrustumPlanes,
+    color::Color,
     instance::Instance,
-    projection::{make_projection_matrix, project_triangles},
+    pipeline::{BackfaceCullingMode, GeometryPass},
+    projection::make_projection_matrix,
     render::parallelizable_draw_triangle,
+    shadow::{ShadowMap, SHADOW_MAP_SIZE},
 };
 
 const FRAMES_PER_SEC: f32 = 30.0;
 const FRAME_TARGET_TIME_MS: f32 = 1000.0 / FRAMES_PER_SEC;
 const FRAME_TARGET_TIME_NS: u32 = (1000.0 * FRAME_TARGET_TIME_MS) as u32;
 const CAMERA_UNITS_PER_FRAME: f32 = 2.0 * (1.0 / FRAMES_PER_SEC); // speed in units / frame
+const ASSET_RELOAD_INTERVAL: Duration = Duration::from_secs(1); // how often loaded files are checked for changes
+const A_BUFFER_MAX_FRAGMENTS: usize = 4; // transparent fragments kept per pixel for order-independent transparency
+const ZNEAR: f32 = 0.1;
+const ZFAR: f32 = 20.0;
+const FRAME_STATS_CSV_PATH: &str = "frame_stats.csv"; // where per-frame pipeline statistics are recorded
+const HUD_MARGIN: i32 = 8; // distance in pixels from the HUD's text to the edges of its panel and the window
 
 #[derive(PartialEq)]
 enum RenderMode {
@@ -60,26 +97,84 @@ enum RenderMode {
     TexturedTriangles,
     WireframeTexturedTriangles,
     ParallelRasterizationTest,
+    GouraudFilledTriangles,
+    GouraudTexturedTriangles,
+    PhongFilledTriangles,
+    PhongTexturedTriangles,
+    Depth,
+    ViewNormals,
+    WorldNormals,
+    TextureCoordinates,
+    Overdraw,
+    TriangleArea,
 }
 
-#[derive(PartialEq)]
-enum BackfaceCullingMode {
-    Enabled,
+impl RenderMode {
+    fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Wireframe => "wireframe",
+            RenderMode::WireframeVertices => "wireframe with vertices",
+            RenderMode::FilledTriangles => "filled",
+            RenderMode::WireframeFilledTriangles => "filled with wireframe",
+            RenderMode::TexturedTriangles => "textured",
+            RenderMode::WireframeTexturedTriangles => "textured with wireframe",
+            RenderMode::ParallelRasterizationTest => {
+                "parallel rasterization test"
+            }
+            RenderMode::GouraudFilledTriangles => "Gouraud filled",
+            RenderMode::GouraudTexturedTriangles => "Gouraud textured",
+            RenderMode::PhongFilledTriangles => "Phong filled",
+            RenderMode::PhongTexturedTriangles => "Phong textured",
+            RenderMode::Depth => "depth",
+            RenderMode::ViewNormals => "view space normals",
+            RenderMode::WorldNormals => "world space normals",
+            RenderMode::TextureCoordinates => "texture coordinates",
+            RenderMode::Overdraw => "overdraw",
+            RenderMode::TriangleArea => "triangle area",
+        }
+    }
+
+    fn is_textured(&self) -> bool {
+        *self == RenderMode::TexturedTriangles
+            || *self == RenderMode::WireframeTexturedTriangles
+            || *self == RenderMode::GouraudTexturedTriangles
+            || *self == RenderMode::PhongTexturedTriangles
+    }
+
+    fn draws_wireframe(&self) -> bool {
+        *self == RenderMode::Wireframe
+            || *self == RenderMode::WireframeVertices
+            || *self == RenderMode::WireframeFilledTriangles
+            || *self == RenderMode::WireframeTexturedTriangles
+    }
+}
+
+/// How edges are anti-aliased. Multisampling shades each pixel once but
+/// tests coverage and depth for several samples. Supersampling renders the
+/// whole frame at a multiple of the window size and scales it down.
+#[derive(Clone, Copy, PartialEq)]
+enum AntiAliasingMode {
     Disabled,
+    Multisample(usize),
+    Supersample(usize),
 }
 
 pub fn main() -> ExitCode {
-    // Load meshes and textures
-    let (meshes, textures) = {
+    // Load meshes and textures. Each model is a mesh along with the texture
+    // its instance is drawn with.
+    let mut assets = AssetManager::new();
+    let models: Vec<(MeshHandle, TextureHandle)> = {
         // Grab arguments
         let args: Vec<String> = env::args().collect();
 
         if args.len() == 1 {
             println!("No model path passed in. Using in-memory cube data");
-            (vec![load_test_mesh()], vec![load_test_texture()])
+            vec![(
+                assets.add_mesh(load_test_mesh()),
+                assets.add_texture(load_test_texture()),
+            )]
         } else if args.len() > 1 {
-            let mut meshes = vec![];
-            let mut textures = vec![];
+            let mut models = vec![];
 
             for arg_index in 0..(args.len() / 2) {
                 let model_path = match args.get((2 * arg_index) + 1) {
@@ -97,11 +192,27 @@ pub fn main() -> ExitCode {
                     }
                 };
 
-                meshes.push(load_obj_mesh(&model_path));
-                textures.push(load_png_texture(&texture_path));
+                let mesh_handle = match assets.load_mesh(&model_path) {
+                    Ok(mesh_handle) => mesh_handle,
+                    Err(error) => {
+                        println!("{}", error);
+                        return ExitCode::from(1);
+                    }
+                };
+                // A missing texture isn't fatal. The model is drawn with the
+                // missing texture checkerboard instead.
+                let texture_handle = match assets.load_texture(&texture_path) {
+                    Ok(texture_handle) => texture_handle,
+                    Err(error) => {
+                        println!("{}", error);
+                        TextureHandle::invalid()
+                    }
+                };
+
+                models.push((mesh_handle, texture_handle));
             }
 
-            (meshes, textures)
+            models
         } else {
             println!("Bad arguments");
             return ExitCode::from(1);
@@ -164,15 +275,22 @@ pub fn main() -> ExitCode {
     // Initialize triangle buffer
     let mut triangles_to_render: Vec<Triangle> = Vec::new();
 
+    // Initialize the normal overlay. Each line runs from a face's center
+    // along its normal, in screen space.
+    let mut normal_overlay_enabled = false;
+    let mut normal_lines: Vec<(Vector2, Vector2)> = Vec::new();
+
     // Initialize camera
     let mut camera = Camera::new();
 
     // Initialize instances of our models
-    // Currently, we only have one instance of each mesh that was passed in to the arguments
+    // Currently, we only have one instance of each model that was passed in to the arguments
     // Instances will be spaced out along the x axis
     let instances: Vec<Instance> = {
         let mut instances = vec![];
-        for mesh_index in 0..meshes.len() {
+        for (model_index, (mesh_handle, texture_handle)) in
+            models.iter().enumerate()
+        {
             instances.push(Instance {
                 orientation: Vector4 {
                     x: 0.0,
@@ -181,41 +299,152 @@ pub fn main() -> ExitCode {
                     w: 1.0,
                 },
                 translation: Vector4 {
-                    x: 5.0 * mesh_index as f32,
+                    x: 5.0 * model_index as f32,
                     y: 0.0,
                     z: 5.0,
                     w: 1.0,
                 },
                 scale: 1.0,
-                mesh_handle: mesh_index,
-                texture_handle: mesh_index,
+                mesh_handle: *mesh_handle,
+                texture_handle: *texture_handle,
             })
         }
 
         instances
     };
 
-    // Initialize light source
-    // NOTE: the light direction is in camera space, not world space, since it is not
-    // transformed via the view matrix
+    // Initialize lights. Lights are defined in world space.
     let mut lighting_enabled = true;
-    let camera_light_source = LightSource::new(Vector3 {
-        x: 0.0,
-        y: 0.0,
-        z: 1.0,
-    });
+    let mut lights: Vec<Light> = vec![
+        Light::ambient(Color::WHITE, 0.1),
+        Light::directional(
+            Vector3 {
+                x: 0.5,
+                y: -1.0,
+                z: 1.0,
+            },
+            Color::WHITE,
+            0.8,
+        ),
+        Light::point(
+            Vector3 {
+                x: -2.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            Attenuation {
+                constant: 1.0,
+                linear: 0.1,
+                quadratic: 0.05,
+            },
+            Color::rgb(1.0, 0.8, 0.6),
+            1.0,
+        ),
+        Light {
+            enabled: false,
+            ..Light::spot(
+                Vector3 {
+                    x: 0.0,
+                    y: 3.0,
+                    z: 0.0,
+                },
+                Vector3 {
+                    x: 0.0,
+                    y: -1.0,
+                    z: 1.0,
+                },
+                0.2,
+                0.35,
+                Attenuation {
+                    constant: 1.0,
+                    linear: 0.0,
+                    quadratic: 0.01,
+                },
+                Color::rgb(0.6, 0.8, 1.0),
+                1.5,
+            )
+        },
+    ];
+    let mut selected_light: usize = 0;
+
+    // Initialize shadow maps. shadow_maps[i] is used for lights[i].
+    let mut shadows_enabled = true;
+    let mut shadow_depth_bias: f32 = 0.05;
+    let mut shadow_maps: Vec<ShadowMap> = lights
+        .iter()
+        .map(|_| ShadowMap::new(SHADOW_MAP_SIZE))
+        .collect();
+
+    // Initialize texture sampling
+    let mut sampler = Sampler::default();
+
+    // Initialize alpha handling. None uses the alpha mode of each material.
+    let mut alpha_mode_override: Option<AlphaMode> = None;
+
+    // Initialize anti-aliasing. When supersampling, the frame is rendered
+    // into supersample_buffer and then scaled down into color_buffer.
+    let mut anti_aliasing_mode = AntiAliasingMode::Disabled;
+    let mut supersample_buffer: Option<ColorBuffer> = None;
+    let mut a_buffer_enabled = false;
+    let mut fxaa_enabled = false;
+
+    // Initialize occlusion culling. When enabled, the opaque triangles of
+    // each instance are drawn into a zbuffer that is reduced to a depth
+    // pyramid, and instances and triangles that are hidden behind it are
+    // skipped.
+    let mut occlusion_culling_enabled = false;
+    let mut occlusion_culler =
+        OcclusionCuller::new(window_width as usize, window_height as usize);
+
+    // Initialize wireframe line drawing
+    let mut line_style = LineStyle::default();
+    // When set, wireframe edges are depth tested and hidden edges are drawn
+    // according to it
+    let mut hidden_line_mode: Option<HiddenLineMode> = None;
 
     // Initialize render mode
     let mut render_mode: RenderMode = RenderMode::FilledTriangles;
     let mut culling_mode: BackfaceCullingMode = BackfaceCullingMode::Enabled;
+    let mut depth_false_color = false;
+
+    // Initialize the HUD. Frame times are measured from the start of one
+    // frame to the start of the next, and busy times exclude the sleep at the
+    // end of each frame.
+    let mut hud_enabled = false;
+    let mut last_frame_start_time = Instant::now();
+    let mut busy_time = Duration::ZERO;
 
-    canvas.set_draw_color(Color::RGB(0xFE, 0x03, 0x6A));
+    // Initialize pipeline statistics. When recording, the stats of every
+    // frame are appended to a CSV file.
+    let mut frame_stats_csv: Option<FrameStatsCsv> = None;
+
+    canvas.set_draw_color(SdlColor::RGB(0xFE, 0x03, 0x6A));
     canvas.clear();
     canvas.present();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut last_asset_reload_time = Instant::now();
     'running: loop {
         let frame_start_time = Instant::now();
+        let frame_time = frame_start_time.duration_since(last_frame_start_time);
+        last_frame_start_time = frame_start_time;
+        let mut frame_stats = FrameStats::default();
+
+        // Pick up any meshes or textures that were re-exported while the
+        // viewer is running
+        if frame_start_time.duration_since(last_asset_reload_time)
+            >= ASSET_RELOAD_INTERVAL
+        {
+            assets.reload_changed_assets();
+            last_asset_reload_time = frame_start_time;
+        }
+
+        // Triangles are projected to the size of the buffer they are drawn
+        // into, which is larger than the window when supersampling
+        let (render_width, render_height) = match &supersample_buffer {
+            Some(buffer) => (buffer.width as u32, buffer.height as u32),
+            None => (window_width, window_height),
+        };
 
         let (camera_direction, camera_right) = {
             // direction that the camera is pointing relative to the camera's origin
@@ -362,6 +591,258 @@ pub fn main() -> ExitCode {
                 } => {
                     lighting_enabled = !lighting_enabled;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::H),
+                    ..
+                } => {
+                    shadows_enabled = !shadows_enabled;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::B),
+                    ..
+                } => {
+                    sampler.filter_mode = match sampler.filter_mode {
+                        FilterMode::Nearest => FilterMode::Bilinear,
+                        FilterMode::Bilinear => FilterMode::Nearest,
+                    };
+                    println!(
+                        "Texture filtering: {}",
+                        sampler.filter_mode.name()
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => {
+                    sampler.mipmap_mode = match sampler.mipmap_mode {
+                        MipmapMode::Disabled => MipmapMode::Nearest,
+                        MipmapMode::Nearest => MipmapMode::Linear,
+                        MipmapMode::Linear => MipmapMode::Disabled,
+                    };
+                    println!("Mipmapping: {}", sampler.mipmap_mode.name());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    sampler.max_anisotropy =
+                        if sampler.max_anisotropy > 1 { 1 } else { 8 };
+                    println!("Max anisotropy: {}x", sampler.max_anisotropy);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::O),
+                    ..
+                } => {
+                    alpha_mode_override = match alpha_mode_override {
+                        None => Some(AlphaMode::Opaque),
+                        Some(AlphaMode::Opaque) => Some(AlphaMode::Test {
+                            cutoff: DEFAULT_ALPHA_CUTOFF,
+                        }),
+                        Some(AlphaMode::Test { .. }) => Some(AlphaMode::Blend),
+                        Some(AlphaMode::Blend) => None,
+                    };
+                    match alpha_mode_override {
+                        Some(alpha_mode) => {
+                            println!("Alpha mode: {}", alpha_mode.name())
+                        }
+                        None => {
+                            println!("Alpha mode: per material and texture")
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::U),
+                    ..
+                } => {
+                    a_buffer_enabled = !a_buffer_enabled;
+                    color_buffer.set_a_buffer_enabled(
+                        a_buffer_enabled,
+                        A_BUFFER_MAX_FRAGMENTS,
+                    );
+                    if let Some(supersample_buffer) = &mut supersample_buffer {
+                        supersample_buffer.set_a_buffer_enabled(
+                            a_buffer_enabled,
+                            A_BUFFER_MAX_FRAGMENTS,
+                        );
+                    }
+                    println!(
+                        "Order-independent transparency: {}",
+                        a_buffer_enabled
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::G),
+                    ..
+                } => {
+                    line_style.anti_aliased = !line_style.anti_aliased;
+                    println!("Anti-aliased lines: {}", line_style.anti_aliased);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::J),
+                    ..
+                } => {
+                    line_style.thickness = line_style.thickness % 3 + 1;
+                    println!("Line thickness: {}", line_style.thickness);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::E),
+                    ..
+                } => {
+                    hidden_line_mode = match hidden_line_mode {
+                        None => Some(HiddenLineMode::Removed),
+                        Some(HiddenLineMode::Removed) => {
+                            Some(HiddenLineMode::Dashed)
+                        }
+                        Some(HiddenLineMode::Dashed) => {
+                            Some(HiddenLineMode::Dimmed)
+                        }
+                        Some(HiddenLineMode::Dimmed) => None,
+                    };
+                    match hidden_line_mode {
+                        Some(hidden_line_mode) => {
+                            println!(
+                                "Hidden lines: {}",
+                                hidden_line_mode.name()
+                            )
+                        }
+                        None => println!("Hidden lines: shown"),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F),
+                    ..
+                } => {
+                    fxaa_enabled = !fxaa_enabled;
+                    println!("FXAA: {}", fxaa_enabled);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Y),
+                    ..
+                } => {
+                    occlusion_culling_enabled = !occlusion_culling_enabled;
+                    println!(
+                        "Occlusion culling: {}",
+                        occlusion_culling_enabled
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::X),
+                    ..
+                } => {
+                    anti_aliasing_mode = match anti_aliasing_mode {
+                        AntiAliasingMode::Disabled => {
+                            AntiAliasingMode::Multisample(2)
+                        }
+                        AntiAliasingMode::Multisample(2) => {
+                            AntiAliasingMode::Multisample(4)
+                        }
+                        AntiAliasingMode::Multisample(4) => {
+                            AntiAliasingMode::Multisample(8)
+                        }
+                        AntiAliasingMode::Multisample(_) => {
+                            AntiAliasingMode::Supersample(2)
+                        }
+                        AntiAliasingMode::Supersample(_) => {
+                            AntiAliasingMode::Disabled
+                        }
+                    };
+
+                    match anti_aliasing_mode {
+                        AntiAliasingMode::Disabled => {
+                            color_buffer.set_sample_count(1);
+                            supersample_buffer = None;
+                            println!("Anti-aliasing: disabled");
+                        }
+                        AntiAliasingMode::Multisample(sample_count) => {
+                            color_buffer.set_sample_count(sample_count);
+                            supersample_buffer = None;
+                            println!("Anti-aliasing: {}x MSAA", sample_count);
+                        }
+                        AntiAliasingMode::Supersample(factor) => {
+                            color_buffer.set_sample_count(1);
+                            let mut buffer = ColorBuffer::new(
+                                factor * window_width as usize,
+                                factor * window_height as usize,
+                            );
+                            buffer.set_a_buffer_enabled(
+                                a_buffer_enabled,
+                                A_BUFFER_MAX_FRAGMENTS,
+                            );
+                            supersample_buffer = Some(buffer);
+                            println!(
+                                "Anti-aliasing: {}x SSAA",
+                                factor * factor
+                            );
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::LeftBracket),
+                    ..
+                } => {
+                    shadow_depth_bias = (shadow_depth_bias - 0.01).max(0.0);
+                    println!("Shadow depth bias: {:.2}", shadow_depth_bias);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::RightBracket),
+                    ..
+                } => {
+                    shadow_depth_bias += 0.01;
+                    println!("Shadow depth bias: {:.2}", shadow_depth_bias);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    selected_light = (selected_light + 1) % lights.len();
+                    let light = &lights[selected_light];
+                    println!(
+                        "Selected {} light {} (enabled: {}, intensity: {:.1})",
+                        light.name(),
+                        selected_light,
+                        light.enabled,
+                        light.intensity
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => {
+                    let light = &mut lights[selected_light];
+                    light.enabled = !light.enabled;
+                    println!(
+                        "{} light {} enabled: {}",
+                        light.name(),
+                        selected_light,
+                        light.enabled
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                } => {
+                    let light = &mut lights[selected_light];
+                    light.intensity += 0.1;
+                    println!(
+                        "{} light {} intensity: {:.1}",
+                        light.name(),
+                        selected_light,
+                        light.intensity
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                } => {
+                    let light = &mut lights[selected_light];
+                    light.intensity = (light.intensity - 0.1).max(0.0);
+                    println!(
+                        "{} light {} intensity: {:.1}",
+                        light.name(),
+                        selected_light,
+                        light.intensity
+                    );
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::C),
                     ..
@@ -416,6 +897,104 @@ pub fn main() -> ExitCode {
                 } => {
                     render_mode = RenderMode::ParallelRasterizationTest;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::_8),
+                    ..
+                } => {
+                    render_mode = RenderMode::GouraudFilledTriangles;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::_9),
+                    ..
+                } => {
+                    render_mode = RenderMode::GouraudTexturedTriangles;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::_0),
+                    ..
+                } => {
+                    render_mode = RenderMode::PhongFilledTriangles;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    render_mode = RenderMode::PhongTexturedTriangles;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    render_mode = RenderMode::Depth;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    render_mode = RenderMode::ViewNormals;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    render_mode = RenderMode::WorldNormals;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    render_mode = RenderMode::TextureCoordinates;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    render_mode = RenderMode::Overdraw;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    render_mode = RenderMode::TriangleArea;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Q),
+                    ..
+                } => {
+                    normal_overlay_enabled = !normal_overlay_enabled;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    hud_enabled = !hud_enabled;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    if frame_stats_csv.take().is_some() {
+                        println!("Stopped recording frame stats");
+                    } else {
+                        match FrameStatsCsv::create(FRAME_STATS_CSV_PATH) {
+                            Ok(csv) => {
+                                frame_stats_csv = Some(csv);
+                                println!(
+                                    "Recording frame stats to {}",
+                                    FRAME_STATS_CSV_PATH
+                                );
+                            }
+                            Err(error) => println!("{}", error),
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Z),
+                    ..
+                } => {
+                    depth_false_color = !depth_false_color;
+                    println!("Depth false color: {}", depth_false_color);
+                }
                 _ => {}
             }
         }
@@ -428,228 +1007,174 @@ pub fn main() -> ExitCode {
         /*
          * Pipeline stages (in order)
          *
+         * Shadow map rendering (depth only, from each light)
          * World transforms
          * Camera transforms
          * Backface Culling
-         * Light intensity calculation (flat shading)
+         * Lighting calculation (flat and Gouraud shading)
+         * Per-pixel lighting (Phong shading) happens during rasterization
          * Projection, perspective divide, and windowing transforms
          * Frustum clipping
          * Rendering triangles to color buffer
          * Transfer color buffer to window
          */
 
-        // Transform and project
-        {
-            // The view matrix is invariant for each instance
-            let view_matrix = camera.view_matrix();
-
-            let znear = 0.1;
-            let zfar = 20.0;
-
-            // The frustum planes are invariant for each mesh
-            let frustum_planes = FrustumPlanes::new(znear, zfar, fov_x, fov_y);
-
-            // The projection matrix is invariant for each mesh
-            let projection_matrix =
-                make_projection_matrix(fov_y, aspect_ratio_y, znear, zfar);
-
-            triangles_to_render.clear();
-
-            // Transform and project meshes
-            for current_instance in &instances {
-                // World matrix is invariant for each face
-                let world_matrix = {
-                    let world_matrix = Matrix4::identity();
-
-                    let world_matrix = Matrix4::mult_mat4(
-                        &Matrix4::scale(
-                            current_instance.scale,
-                            current_instance.scale,
-                            current_instance.scale,
-                        ),
-                        &world_matrix,
-                    );
-                    let world_matrix = Matrix4::mult_mat4(
-                        &Matrix4::rotate_around_x(
-                            current_instance.orientation.x,
-                        ),
-                        &world_matrix,
-                    );
-                    let world_matrix = Matrix4::mult_mat4(
-                        &Matrix4::rotate_around_y(
-                            current_instance.orientation.y,
-                        ),
-                        &world_matrix,
-                    );
-                    let world_matrix = Matrix4::mult_mat4(
-                        &Matrix4::rotate_around_z(
-                            current_instance.orientation.z,
-                        ),
-                        &world_matrix,
-                    );
-
-                    let translation_matrix = Matrix4::translate(
-                        current_instance.translation.x,
-                        current_instance.translation.y,
-                        current_instance.translation.z,
-                    );
-                    let world_matrix =
-                        Matrix4::mult_mat4(&translation_matrix, &world_matrix);
-
-                    world_matrix
-                };
+        // The view matrix is invariant for each instance
+        let view_matrix = camera.view_matrix();
 
-                let current_mesh = &meshes[current_instance.mesh_handle];
-                // Loop over faces
-                for face in &current_mesh.faces {
-                    let vertices: [Vector3; 3] =
-                        current_mesh.get_vertices(face);
+        // Lights are defined in world space, but shading happens in view space
+        let view_lights: Vec<Light> = lights
+            .iter()
+            .map(|light| light.to_view_space(&view_matrix))
+            .collect();
 
-                    let mut transformed_vertices: [Vector4; 3] = [
-                        Vector4 {
-                            ..Default::default()
-                        },
-                        Vector4 {
-                            ..Default::default()
-                        },
-                        Vector4 {
-                            ..Default::default()
-                        },
-                    ];
-
-                    // Transform
-                    for (index, vertex) in vertices.into_iter().enumerate() {
-                        // world transform
-                        let transformed_vertex = Matrix4::mult_vector(
-                            &world_matrix,
-                            &Vector4::from_vector3(&vertex),
-                        );
-                        // view transform
-                        let transformed_vertex = Matrix4::mult_vector(
-                            &view_matrix,
-                            &transformed_vertex,
-                        );
-                        transformed_vertices[index] = transformed_vertex;
-                    }
-
-                    // Pull out face vectors (left-handed system)
-                    /*
-                         A
-                        /  \
-                        C - B
-                    */
-                    let vector_a =
-                        &Vector3::from_vector4(&transformed_vertices[0]);
-                    let vector_b =
-                        &Vector3::from_vector4(&transformed_vertices[1]);
-                    let vector_c =
-                        &Vector3::from_vector4(&transformed_vertices[2]);
-
-                    // Find face normal
-                    let face_normal: Vector3 = {
-                        let ab_vector = {
-                            let mut ab_vector = vector_b - vector_a;
-                            ab_vector.normalize();
-                            ab_vector
-                        };
-                        let ac_vector = {
-                            let mut ac_vector = vector_c - vector_a;
-                            ac_vector.normalize();
-                            ac_vector
-                        };
-                        let face_normal = {
-                            let mut face_normal =
-                                calc_cross_product(&ab_vector, &ac_vector);
-                            face_normal.normalize();
-                            face_normal
-                        };
-
-                        face_normal
-                    };
-
-                    // Backface culling
-                    let culled: bool = if culling_mode
-                        == BackfaceCullingMode::Enabled
-                    {
-                        // Calculate the to-camera vector.
-                        // Since this is performed after the view matrix transform,
-                        // the camera is at the origin
-                        let face_to_camera = -1.0 * vector_a;
-
-                        let dot_product =
-                            Vector3::dot_product(&face_normal, &face_to_camera);
-
-                        dot_product < 0.0
-                    } else {
-                        false
-                    };
+        // Render shadow maps
+        {
+            let shadow_start_time = Instant::now();
+
+            // The shadow maps cover a sphere around all of the instances
+            let (shadow_focus, shadow_radius) = {
+                let mut focus = Vector3::default();
+                for instance in &instances {
+                    focus =
+                        &focus + &Vector3::from_vector4(&instance.translation);
+                }
+                let focus = (1.0 / instances.len() as f32) * &focus;
+
+                let mut radius: f32 = 0.0;
+                for instance in &instances {
+                    let distance =
+                        (&Vector3::from_vector4(&instance.translation)
+                            - &focus)
+                            .magnitude();
+                    let mesh_radius =
+                        match assets.get_mesh(instance.mesh_handle) {
+                            Some(mesh) => mesh.calc_bounding_radius(),
+                            None => 0.0,
+                        } * instance.scale;
+                    radius = radius.max(distance + mesh_radius);
+                }
 
-                    // Project
-                    if !culled {
-                        // Lighting
-                        // Note that lighting is currently applied *after* the view matrix transform, which means the
-                        // "direction" of the light is always from the camera position.
-                        let light_intensity: f32 = if lighting_enabled {
-                            let dot_product = Vector3::dot_product(
-                                &face_normal,
-                                &camera_light_source.direction,
-                            );
+                (focus, radius)
+            };
 
-                            /*
-                             * If the dot product is negative, then the normal and the light are pointing in opposite directions,
-                             * which means that there should be light
-                             *
-                             * If the dot product is 0, then the normal and the light are orthogonal, and there should be no light.
-                             *
-                             * If the dot product is positive, then the normal is pointing in the opposite direction of the light, and there
-                             * should be no light.
-                             *
-                             * Note that if both vectors are normalized, then the dot product shall be in the range [-1.0, 1.0]
-                             */
-                            if dot_product < 0.0 {
-                                -1.0 * dot_product
-                            } else {
-                                0.0
-                            }
-                        } else {
-                            1.0
-                        };
+            for (light, shadow_map) in lights.iter().zip(shadow_maps.iter_mut())
+            {
+                shadow_map.depth_bias = shadow_depth_bias;
+
+                if !shadows_enabled
+                    || !lighting_enabled
+                    || !light.enabled
+                    || !shadow_map.setup(light, &shadow_focus, shadow_radius)
+                {
+                    shadow_map.active = false;
+                    continue;
+                }
 
-                        let triangle = Triangle {
-                            points: transformed_vertices.clone(),
-                            texel_coordinates: current_mesh
-                                .get_texel_coordinates(face),
-                            color: face.color,
-                            light_intensity,
-                            texture_handle: current_instance.texture_handle,
-                            ..Default::default()
+                for current_instance in &instances {
+                    let world_matrix = current_instance.world_matrix();
+                    let current_mesh =
+                        match assets.get_mesh(current_instance.mesh_handle) {
+                            Some(mesh) => mesh,
+                            None => continue,
                         };
 
-                        let mut triangles =
-                            clip_triangle(&frustum_planes, triangle);
+                    for face in &current_mesh.faces {
+                        let world_vertices =
+                            current_mesh.get_vertices(face).map(|vertex| {
+                                Vector3::from_vector4(&Matrix4::mult_vector(
+                                    &world_matrix,
+                                    &Vector4::from_vector3(&vertex),
+                                ))
+                            });
 
-                        project_triangles(
-                            &projection_matrix,
-                            window_width,
-                            window_height,
-                            &mut triangles,
-                            &mut triangles_to_render,
-                        );
+                        shadow_map.draw_triangle(&world_vertices);
                     }
                 }
             }
+
+            frame_stats.shadow_time = shadow_start_time.elapsed();
+        }
+
+        // Wireframe only modes draw every triangle, so nothing is culled
+        let occlusion_culling_active = occlusion_culling_enabled
+            && !(render_mode == RenderMode::Wireframe
+                || render_mode == RenderMode::WireframeVertices
+                || render_mode == RenderMode::ParallelRasterizationTest);
+        if occlusion_culling_active {
+            occlusion_culler
+                .clear(render_width as usize, render_height as usize);
+        }
+
+        // Transform and project
+        {
+            let geometry_pass = GeometryPass {
+                view_matrix,
+                // The projection matrix and frustum planes are invariant for
+                // each mesh
+                projection_matrix: make_projection_matrix(
+                    fov_y,
+                    aspect_ratio_y,
+                    ZNEAR,
+                    ZFAR,
+                ),
+                frustum_planes: FrustumPlanes::new(ZNEAR, ZFAR, fov_x, fov_y),
+                width: render_width,
+                height: render_height,
+                culling_mode,
+                lighting_enabled,
+                lights: &view_lights,
+                shadow_maps: &shadow_maps,
+                textured: render_mode.is_textured(),
+                alpha_mode_override,
+                normal_overlay_enabled,
+                // Occluded instances would be missing from wireframes and
+                // the normal overlay, which draw over hidden surfaces
+                cull_occluded_instances: !render_mode.draws_wireframe()
+                    && !normal_overlay_enabled,
+            };
+
+            geometry_pass.transform_instances(
+                &instances,
+                &assets,
+                occlusion_culling_active.then_some(&mut occlusion_culler),
+                &mut triangles_to_render,
+                &mut normal_lines,
+                &mut frame_stats,
+            );
         }
 
         // Render
         {
-            color_buffer.clear(0xFF000000);
+            let raster_start_time = Instant::now();
+
+            let render_target = match &mut supersample_buffer {
+                Some(buffer) => buffer,
+                None => &mut color_buffer,
+            };
+
+            render_target
+                .set_overdraw_enabled(render_mode == RenderMode::Overdraw);
+            render_target.clear(0xFF000000);
+
+            sort_triangles_for_blending(&mut triangles_to_render);
+
+            // Set occluded triangles aside while filling. They are put back
+            // afterwards so that wireframes still show them.
+            let mut occluded_triangles = Vec::new();
+            if occlusion_culling_active {
+                occluded_triangles = occlusion_culler.cull_occluded_triangles(
+                    &mut triangles_to_render,
+                    &mut frame_stats,
+                );
+            }
 
             if render_mode == RenderMode::FilledTriangles
                 || render_mode == RenderMode::WireframeFilledTriangles
             {
                 for triangle in &triangles_to_render {
                     draw_filled_triangle(
-                        &mut color_buffer,
+                        render_target,
                         triangle,
                         triangle.color,
                     );
@@ -658,34 +1183,172 @@ pub fn main() -> ExitCode {
                 || render_mode == RenderMode::WireframeTexturedTriangles
             {
                 for triangle in &triangles_to_render {
-                    let texture = &textures[triangle.texture_handle];
+                    let texture = assets.get_texture(triangle.texture_handle);
                     draw_textured_triangle(
-                        &mut color_buffer,
-                        &triangle,
+                        render_target,
+                        triangle,
                         texture,
+                        &sampler,
+                    );
+                }
+            } else if render_mode == RenderMode::GouraudFilledTriangles {
+                for triangle in &triangles_to_render {
+                    draw_gouraud_triangle(
+                        render_target,
+                        triangle,
+                        triangle.color,
+                    );
+                }
+            } else if render_mode == RenderMode::GouraudTexturedTriangles {
+                for triangle in &triangles_to_render {
+                    let texture = assets.get_texture(triangle.texture_handle);
+                    draw_gouraud_textured_triangle(
+                        render_target,
+                        triangle,
+                        texture,
+                        &sampler,
+                    );
+                }
+            } else if render_mode == RenderMode::PhongFilledTriangles {
+                for triangle in &triangles_to_render {
+                    if lighting_enabled {
+                        draw_phong_triangle(
+                            render_target,
+                            triangle,
+                            triangle.color,
+                            &view_lights,
+                            &shadow_maps,
+                        );
+                    } else {
+                        draw_filled_triangle(
+                            render_target,
+                            triangle,
+                            triangle.color,
+                        );
+                    }
+                }
+            } else if render_mode == RenderMode::PhongTexturedTriangles {
+                for triangle in &triangles_to_render {
+                    let texture = assets.get_texture(triangle.texture_handle);
+                    if lighting_enabled {
+                        draw_phong_textured_triangle(
+                            render_target,
+                            triangle,
+                            texture,
+                            &sampler,
+                            &view_lights,
+                            &shadow_maps,
+                        );
+                    } else {
+                        draw_textured_triangle(
+                            render_target,
+                            triangle,
+                            texture,
+                            &sampler,
+                        );
+                    }
+                }
+            } else if render_mode == RenderMode::Depth {
+                for triangle in &triangles_to_render {
+                    draw_depth_triangle(render_target, triangle);
+                }
+                draw_depth_buffer(
+                    render_target,
+                    ZNEAR,
+                    ZFAR,
+                    depth_false_color,
+                );
+            } else if render_mode == RenderMode::ViewNormals {
+                for triangle in &triangles_to_render {
+                    draw_normal_triangle(
+                        render_target,
+                        triangle,
+                        NORMAL_VARYING,
                     );
                 }
+            } else if render_mode == RenderMode::WorldNormals {
+                for triangle in &triangles_to_render {
+                    draw_normal_triangle(
+                        render_target,
+                        triangle,
+                        WORLD_NORMAL_VARYING,
+                    );
+                }
+            } else if render_mode == RenderMode::TextureCoordinates {
+                for triangle in &triangles_to_render {
+                    draw_uv_triangle(render_target, triangle);
+                }
+            } else if render_mode == RenderMode::Overdraw {
+                for triangle in &triangles_to_render {
+                    draw_filled_triangle(
+                        render_target,
+                        triangle,
+                        triangle.color,
+                    );
+                }
+            } else if render_mode == RenderMode::TriangleArea {
+                for triangle in &triangles_to_render {
+                    draw_area_triangle(render_target, triangle);
+                }
             }
 
-            if !(render_mode == RenderMode::FilledTriangles
-                || render_mode == RenderMode::TexturedTriangles
-                || render_mode == RenderMode::ParallelRasterizationTest)
-            {
+            // Average the samples of each pixel, then composite transparent
+            // fragments collected by the A-buffer over the result
+            render_target.resolve_samples();
+
+            render_target.fill_raster_stats(&mut frame_stats);
+            render_target.resolve_transparency();
+            triangles_to_render.append(&mut occluded_triangles);
+
+            if render_mode.draws_wireframe() {
+                // Wireframe only modes have nothing in the zbuffer to test
+                // edges against, so fill it with a depth prepass first
+                if hidden_line_mode.is_some()
+                    && (render_mode == RenderMode::Wireframe
+                        || render_mode == RenderMode::WireframeVertices)
+                {
+                    for triangle in &triangles_to_render {
+                        draw_depth_triangle(render_target, triangle);
+                    }
+                }
+
                 for triangle in &triangles_to_render {
-                    draw_triangle(&mut color_buffer, triangle, 0xFFFFFFFF);
+                    draw_triangle(
+                        render_target,
+                        triangle,
+                        0xFFFFFFFF,
+                        &line_style,
+                        hidden_line_mode,
+                    );
                 }
             }
 
             if render_mode == RenderMode::WireframeVertices {
                 for triangle in &triangles_to_render {
-                    draw_triangle_vertices(
-                        &mut color_buffer,
-                        triangle,
-                        0xFFFF0000,
+                    draw_triangle_vertices(render_target, triangle, 0xFFFF0000);
+                }
+            }
+
+            if normal_overlay_enabled {
+                for (start, end) in &normal_lines {
+                    draw_styled_line(
+                        render_target,
+                        start,
+                        end,
+                        0xFFFFFF00,
+                        &line_style,
                     );
                 }
             }
 
+            // The overdraw heatmap replaces everything that was drawn, so it
+            // comes after all other drawing
+            if render_mode == RenderMode::Overdraw {
+                draw_overdraw_heatmap(render_target);
+            } else if render_mode == RenderMode::TriangleArea {
+                draw_area_legend(render_target);
+            }
+
             if render_mode == RenderMode::ParallelRasterizationTest {
                 let triangle = Triangle {
                     points: [
@@ -711,9 +1374,94 @@ pub fn main() -> ExitCode {
                     color: 0xFFFFFFFF,
                     ..Default::default()
                 };
-                parallelizable_draw_triangle(&mut color_buffer, &triangle);
+                parallelizable_draw_triangle(render_target, &triangle);
+            }
+
+            if let Some(supersample_buffer) = &supersample_buffer {
+                supersample_buffer.downsample_into(&mut color_buffer);
+            }
+
+            // Post-process the final image
+            if fxaa_enabled {
+                apply_fxaa(&mut color_buffer);
+            }
+
+            // The HUD is drawn last so that it isn't anti-aliased or covered
+            if hud_enabled {
+                let anti_aliasing = match anti_aliasing_mode {
+                    AntiAliasingMode::Disabled => "off".to_string(),
+                    AntiAliasingMode::Multisample(sample_count) => {
+                        format!("{}x MSAA", sample_count)
+                    }
+                    AntiAliasingMode::Supersample(factor) => {
+                        format!("{}x SSAA", factor * factor)
+                    }
+                };
+                let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+
+                let hud_text = format!(
+                    "FPS: {:.1} ({:.1} ms per frame, {:.1} ms busy)\n\
+                     Instances: {} ({} occluded)\n\
+                     Faces: {} ({} culled, {} clipped away)\n\
+                     Triangles: {} after clipping, {} occluded, {} rasterized\n\
+                     Pixels shaded: {}\n\
+                     Render mode: {}\n\
+                     Culling: {}  Lighting: {}  Shadows: {}\n\
+                     Anti-aliasing: {}  FXAA: {}  Occlusion culling: {}\n\
+                     \n\
+                     W/S/A/D/I/K move, arrows turn\n\
+                     1-9, 0, P, F1-F6 render mode\n\
+                     C/V culling on/off, L lighting, H shadows\n\
+                     Tab select light, T toggle it, -/= intensity\n\
+                     [/] shadow bias\n\
+                     B filter, N mipmaps, M anisotropy\n\
+                     O alpha mode, U order-independent transparency\n\
+                     X anti-aliasing, F FXAA, Y occlusion culling\n\
+                     G smooth lines, J line thickness, E hidden lines\n\
+                     Q normals, Z depth false color\n\
+                     F11 record frame stats, F12 HUD, Esc quit",
+                    1.0 / frame_time.as_secs_f32().max(f32::EPSILON),
+                    1000.0 * frame_time.as_secs_f32(),
+                    1000.0 * busy_time.as_secs_f32(),
+                    frame_stats.instance_count,
+                    frame_stats.occluded_instance_count,
+                    frame_stats.face_count,
+                    frame_stats.culled_face_count,
+                    frame_stats.clipped_face_count,
+                    frame_stats.clipped_triangle_count,
+                    frame_stats.occluded_triangle_count,
+                    frame_stats.rasterized_triangle_count,
+                    frame_stats.shaded_pixel_count,
+                    render_mode.name(),
+                    on_off(culling_mode == BackfaceCullingMode::Enabled),
+                    on_off(lighting_enabled),
+                    on_off(shadows_enabled),
+                    anti_aliasing,
+                    on_off(fxaa_enabled),
+                    on_off(occlusion_culling_enabled),
+                );
+
+                let (text_width, text_height) = measure_text(&hud_text, 1);
+                blend_rect(
+                    &mut color_buffer,
+                    HUD_MARGIN,
+                    HUD_MARGIN,
+                    text_width as i32 + 2 * HUD_MARGIN,
+                    text_height as i32 + 2 * HUD_MARGIN,
+                    0xA0000000,
+                );
+                color_buffer.draw_text(
+                    2 * HUD_MARGIN,
+                    2 * HUD_MARGIN,
+                    &hud_text,
+                    0xFFFFFFFF,
+                    1,
+                );
             }
 
+            let present_start_time = Instant::now();
+            frame_stats.raster_time = present_start_time - raster_start_time;
+
             // write color buffer to texture
             unsafe {
                 color_buffer_texture
@@ -725,9 +1473,19 @@ pub fn main() -> ExitCode {
                 .expect("Failure to copy texture to canvas");
 
             canvas.present();
+
+            frame_stats.present_time = present_start_time.elapsed();
+        }
+
+        if let Some(csv) = &mut frame_stats_csv {
+            if let Err(error) = csv.write(&frame_stats) {
+                println!("{}. Stopped recording frame stats", error);
+                frame_stats_csv = None;
+            }
         }
 
         let frame_duration = Instant::now().duration_since(frame_start_time);
+        busy_time = frame_duration;
         if frame_duration.as_millis() < (FRAME_TARGET_TIME_MS as u128) {
             let sleep_time =
                 frame_duration - Duration::new(0, FRAME_TARGET_TIME_NS);
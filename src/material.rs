@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::Read;
+
+use crate::{color::Color, texture::WrapMode};
+
+/// Alpha below which fragments are discarded when alpha testing
+pub const DEFAULT_ALPHA_CUTOFF: f32 = 0.5;
+
+/// How the alpha of a surface is used when it is drawn
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored and the surface is fully opaque
+    Opaque,
+    /// Fragments with an alpha below the cutoff are discarded and the rest
+    /// are opaque
+    Test { cutoff: f32 },
+    /// Fragments are composited over whatever is behind them (source-over)
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlphaMode::Opaque => "opaque",
+            AlphaMode::Test { .. } => "alpha test",
+            AlphaMode::Blend => "alpha blend",
+        }
+    }
+}
+
+/// Surface properties used when shading a face
+#[derive(Clone, Copy)]
+pub struct Material {
+    /// The alpha of the diffuse color is the opacity of the material
+    pub diffuse_color: Color,
+    pub specular_color: Color,
+    pub shininess: f32,
+    pub alpha_mode: AlphaMode,
+    /// How the texture is wrapped along U and V, from the options of map_Kd
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            diffuse_color: Color::from_argb(0xFFAAAAAA),
+            specular_color: Color::BLACK,
+            shininess: 32.0,
+            alpha_mode: AlphaMode::Opaque,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+        }
+    }
+}
+
+fn parse_rgb(rest_of_line: &str) -> Option<Color> {
+    let mut elements = [0.0; 3];
+    for (index, element_str) in
+        rest_of_line.split_whitespace().take(3).enumerate()
+    {
+        elements[index] = element_str.parse().ok()?;
+    }
+
+    Some(Color::rgb(elements[0], elements[1], elements[2]))
+}
+
+/// Finds the U and V wrap modes in the options of a texture map statement.
+/// '-clamp on|off' is standard and applies to both axes. As a local
+/// extension, '-wrap_u' and '-wrap_v' take "repeat", "clamp" or "mirror" to
+/// set a single axis. Later options override earlier ones, and without any
+/// the texture repeats. Returns None if an option's value is missing or
+/// unknown.
+fn parse_wrap_mode(rest_of_line: &str) -> Option<(WrapMode, WrapMode)> {
+    let mut wrap_u = WrapMode::Repeat;
+    let mut wrap_v = WrapMode::Repeat;
+    let mut elements = rest_of_line.split_whitespace();
+    while let Some(element) = elements.next() {
+        match element {
+            "-clamp" => {
+                let wrap_mode = match elements.next()? {
+                    "on" => WrapMode::ClampToEdge,
+                    "off" => WrapMode::Repeat,
+                    _ => return None,
+                };
+                wrap_u = wrap_mode;
+                wrap_v = wrap_mode;
+            }
+            "-wrap_u" => wrap_u = parse_wrap_mode_name(elements.next()?)?,
+            "-wrap_v" => wrap_v = parse_wrap_mode_name(elements.next()?)?,
+            _ => {}
+        }
+    }
+
+    Some((wrap_u, wrap_v))
+}
+
+fn parse_wrap_mode_name(name: &str) -> Option<WrapMode> {
+    match name {
+        "repeat" => Some(WrapMode::Repeat),
+        "clamp" => Some(WrapMode::ClampToEdge),
+        "mirror" => Some(WrapMode::MirroredRepeat),
+        _ => None,
+    }
+}
+
+/// Sets the opacity of a material. Materials that aren't fully opaque are
+/// blended.
+fn set_opacity(material: &mut Material, opacity: f32) {
+    let opacity = opacity.clamp(0.0, 1.0);
+    material.diffuse_color = material.diffuse_color.with_alpha(opacity);
+    material.alpha_mode = if opacity < 1.0 {
+        AlphaMode::Blend
+    } else {
+        AlphaMode::Opaque
+    };
+}
+
+/// Loads all materials from an MTL file. Returns the name of each material
+/// along with the material.
+pub fn load_mtl_materials(
+    path: &String,
+) -> Result<Vec<(String, Material)>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            return Err(format!("Unable to open {}: {}", path, error))
+        }
+    };
+
+    let mut contents = String::new();
+    match file.read_to_string(&mut contents) {
+        Ok(_) => {}
+        Err(error) => {
+            return Err(format!("Unable to read {}: {}", path, error))
+        }
+    }
+
+    let mut materials: Vec<(String, Material)> = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if let Some(name) = line.strip_prefix("newmtl ") {
+            materials.push((name.trim().to_string(), Material::default()));
+            continue;
+        }
+
+        // Every other property applies to the most recent material
+        let (_, material) = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        if let Some(rest_of_line) = line.strip_prefix("Kd ") {
+            match parse_rgb(rest_of_line) {
+                Some(color) => {
+                    // The opacity may have been set before the color
+                    material.diffuse_color =
+                        color.with_alpha(material.diffuse_color.a)
+                }
+                None => return Err(format!("Bad Kd value in {}", path)),
+            }
+        } else if let Some(rest_of_line) = line.strip_prefix("Ks ") {
+            match parse_rgb(rest_of_line) {
+                Some(color) => material.specular_color = color,
+                None => return Err(format!("Bad Ks value in {}", path)),
+            }
+        } else if let Some(rest_of_line) = line.strip_prefix("d ") {
+            match rest_of_line.trim().parse() {
+                Ok(opacity) => set_opacity(material, opacity),
+                Err(_) => return Err(format!("Bad d value in {}", path)),
+            }
+        } else if let Some(rest_of_line) = line.strip_prefix("Tr ") {
+            // Tr is transparency, the inverse of d
+            match rest_of_line.trim().parse::<f32>() {
+                Ok(transparency) => set_opacity(material, 1.0 - transparency),
+                Err(_) => return Err(format!("Bad Tr value in {}", path)),
+            }
+        } else if let Some(rest_of_line) = line.strip_prefix("map_Kd ") {
+            match parse_wrap_mode(rest_of_line) {
+                Some((wrap_u, wrap_v)) => {
+                    material.wrap_u = wrap_u;
+                    material.wrap_v = wrap_v;
+                }
+                None => return Err(format!("Bad map_Kd options in {}", path)),
+            }
+        } else if let Some(rest_of_line) = line.strip_prefix("Ns ") {
+            match rest_of_line.trim().parse() {
+                Ok(shininess) => material.shininess = shininess,
+                Err(_) => return Err(format!("Bad Ns value in {}", path)),
+            }
+        }
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_wrap_mode_reads_standard_and_per_axis_options() {
+        let cases = [
+            ("texture.png", (WrapMode::Repeat, WrapMode::Repeat)),
+            (
+                "-clamp on texture.png",
+                (WrapMode::ClampToEdge, WrapMode::ClampToEdge),
+            ),
+            (
+                "-clamp off texture.png",
+                (WrapMode::Repeat, WrapMode::Repeat),
+            ),
+            (
+                "-wrap_u mirror texture.png",
+                (WrapMode::MirroredRepeat, WrapMode::Repeat),
+            ),
+            (
+                "-wrap_u clamp -wrap_v mirror texture.png",
+                (WrapMode::ClampToEdge, WrapMode::MirroredRepeat),
+            ),
+            // Later options override earlier ones
+            (
+                "-clamp on -wrap_v repeat texture.png",
+                (WrapMode::ClampToEdge, WrapMode::Repeat),
+            ),
+            // Unrelated options are skipped along with their values
+            (
+                "-bm 0.5 -clamp on texture.png",
+                (WrapMode::ClampToEdge, WrapMode::ClampToEdge),
+            ),
+        ];
+
+        for (options, expected) in cases {
+            assert!(
+                parse_wrap_mode(options) == Some(expected),
+                "wrong wrap modes for '{}'",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn parse_wrap_mode_rejects_missing_and_unknown_values() {
+        for options in [
+            "-clamp",
+            "-clamp yes texture.png",
+            "-wrap_u",
+            "-wrap_v wrap texture.png",
+        ] {
+            assert!(
+                parse_wrap_mode(options).is_none(),
+                "'{}' should be rejected",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn load_mtl_materials_fails_on_bad_map_kd_options() {
+        let path = std::env::temp_dir().join("material_bad_map_kd_options.mtl");
+        std::fs::write(&path, "newmtl crate\nmap_Kd -clamp\n").unwrap();
+
+        let result = load_mtl_materials(&path.to_string_lossy().to_string());
+        assert!(result.is_err());
+    }
+}
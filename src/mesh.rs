@@ -1,14 +1,20 @@
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 
+use crate::material::{load_mtl_materials, Material};
 use crate::texture::TextureUv;
 use crate::triangle::Face;
-use crate::vector::Vector3;
+use crate::vector::{calc_cross_product, Vector3};
 
 pub struct Mesh {
     vertices: Vec<Vector3>,
     texel_coordinates: Vec<TextureUv>,
+    normals: Vec<Vector3>,
+    materials: Vec<Material>,
     pub faces: Vec<Face>,
+    /// Paths of the MTL files the mesh's materials were loaded from
+    pub material_library_paths: Vec<String>,
 }
 
 impl Mesh {
@@ -27,41 +33,162 @@ impl Mesh {
             self.texel_coordinates[face.c_uv - 1].clone(),
         ]
     }
+
+    /// Returns the distance from the origin (in model space) to the vertex
+    /// farthest from it
+    pub fn calc_bounding_radius(&self) -> f32 {
+        self.vertices
+            .iter()
+            .map(|vertex| vertex.magnitude())
+            .fold(0.0, f32::max)
+    }
+
+    /// Returns the minimum and maximum corners (in model space) of the box
+    /// that contains every vertex
+    pub fn calc_bounding_box(&self) -> (Vector3, Vector3) {
+        let mut min = Vector3 {
+            x: f32::MAX,
+            y: f32::MAX,
+            z: f32::MAX,
+        };
+        let mut max = Vector3 {
+            x: f32::MIN,
+            y: f32::MIN,
+            z: f32::MIN,
+        };
+        for vertex in &self.vertices {
+            min.x = min.x.min(vertex.x);
+            min.y = min.y.min(vertex.y);
+            min.z = min.z.min(vertex.z);
+            max.x = max.x.max(vertex.x);
+            max.y = max.y.max(vertex.y);
+            max.z = max.z.max(vertex.z);
+        }
+
+        (min, max)
+    }
+
+    pub fn get_material(&self, face: &Face) -> Material {
+        self.materials[face.material]
+    }
+
+    pub fn get_vertex_normals(&self, face: &Face) -> [Vector3; 3] {
+        [
+            self.normals[face.a_normal - 1],
+            self.normals[face.b_normal - 1],
+            self.normals[face.c_normal - 1],
+        ]
+    }
+}
+
+/// Fills in vertex normals for any faces that don't reference one. The normal
+/// for a vertex is the average of the normals of all faces that share it.
+fn fill_missing_normals(
+    vertices: &[Vector3],
+    normals: &mut Vec<Vector3>,
+    faces: &mut [Face],
+) {
+    if faces.iter().all(|face| {
+        face.a_normal != 0 && face.b_normal != 0 && face.c_normal != 0
+    }) {
+        return;
+    }
+
+    let mut vertex_normals = vec![Vector3::default(); vertices.len()];
+    for face in faces.iter() {
+        let vector_a = &vertices[face.a - 1];
+        let vector_b = &vertices[face.b - 1];
+        let vector_c = &vertices[face.c - 1];
+
+        // Not normalized so that larger faces contribute more to the average
+        let face_normal =
+            calc_cross_product(&(vector_b - vector_a), &(vector_c - vector_a));
+
+        for vertex_index in [face.a, face.b, face.c] {
+            vertex_normals[vertex_index - 1] =
+                &vertex_normals[vertex_index - 1] + &face_normal;
+        }
+    }
+
+    // Smoothed normals are stored after any normals read from the file, so
+    // the index of a vertex's smoothed normal is offset by the existing count
+    let offset = normals.len();
+    for mut normal in vertex_normals {
+        if normal.magnitude() > 0.0 {
+            normal.normalize();
+        }
+        normals.push(normal);
+    }
+
+    for face in faces.iter_mut() {
+        if face.a_normal == 0 || face.b_normal == 0 || face.c_normal == 0 {
+            face.a_normal = offset + face.a;
+            face.b_normal = offset + face.b;
+            face.c_normal = offset + face.c;
+        }
+    }
 }
 
 pub fn load_test_mesh() -> Mesh {
+    let vertices = MESH_VERTICES.to_vec();
+    let mut normals = vec![];
+    let mut faces = MESH_FACES.to_vec();
+
+    fill_missing_normals(&vertices, &mut normals, &mut faces);
+
     Mesh {
-        vertices: MESH_VERTICES.to_vec(),
+        vertices,
         texel_coordinates: TEXEL_COORDINATES.to_vec(),
-        faces: MESH_FACES.to_vec(),
+        normals,
+        materials: vec![Material::default()],
+        faces,
+        material_library_paths: vec![],
     }
 }
 
-pub fn load_obj_mesh(path: &String) -> Mesh {
+pub fn load_obj_mesh(path: &String) -> Result<Mesh, String> {
     let mut vertices = vec![];
     let mut texel_coordinates = vec![];
+    let mut normals = vec![];
     let mut faces = vec![];
 
+    // Material 0 is used by faces that don't specify a material
+    let mut material_names: Vec<String> = vec![String::new()];
+    let mut materials = vec![Material::default()];
+    let mut current_material: usize = 0;
+    let mut material_library_paths = vec![];
+
     let mut file = match File::open(path) {
         Ok(file) => file,
-        Err(_) => todo!(),
+        Err(error) => {
+            return Err(format!("Unable to open {}: {}", path, error))
+        }
     };
 
     let mut contents = String::new();
 
     match file.read_to_string(&mut contents) {
         Ok(_) => {}
-        Err(_) => todo!(),
+        Err(error) => {
+            return Err(format!("Unable to read {}: {}", path, error))
+        }
     }
 
     for line in contents.lines() {
         if line.starts_with("v ") {
             let rest_of_line = &line[2..];
             let mut elements = [0.0; 4];
-            for (index, element_str) in rest_of_line.split(" ").enumerate() {
+            for (index, element_str) in
+                rest_of_line.split_whitespace().take(4).enumerate()
+            {
                 elements[index] = match element_str.parse() {
                     Ok(float) => float,
-                    Err(_) => panic!("Unable to convert vertex to float"),
+                    Err(_) => {
+                        return Err(format!(
+                            "Unable to convert vertex to float in {}",
+                            path
+                        ))
+                    }
                 };
             }
 
@@ -74,10 +201,17 @@ pub fn load_obj_mesh(path: &String) -> Mesh {
         } else if line.starts_with("vt ") {
             let rest_of_line = &line[3..];
             let mut elements = [0.0; 2];
-            for (index, element_str) in rest_of_line.split(" ").enumerate() {
+            for (index, element_str) in
+                rest_of_line.split_whitespace().take(2).enumerate()
+            {
                 elements[index] = match element_str.parse() {
                     Ok(float) => float,
-                    Err(_) => panic!("Unable to convert vertex to float"),
+                    Err(_) => {
+                        return Err(format!(
+                            "Unable to convert texel coordinate to float in {}",
+                            path
+                        ))
+                    }
                 };
             }
 
@@ -85,26 +219,83 @@ pub fn load_obj_mesh(path: &String) -> Mesh {
                 u: elements[0],
                 v: 1.0 - elements[1], // we use top left coordinates
             });
+        } else if line.starts_with("vn ") {
+            let rest_of_line = &line[3..];
+            let mut elements = [0.0; 3];
+            for (index, element_str) in
+                rest_of_line.split_whitespace().take(3).enumerate()
+            {
+                elements[index] = match element_str.parse() {
+                    Ok(float) => float,
+                    Err(_) => {
+                        return Err(format!(
+                            "Unable to convert normal to float in {}",
+                            path
+                        ))
+                    }
+                };
+            }
+
+            let mut normal = Vector3 {
+                x: elements[0],
+                y: elements[1],
+                z: elements[2],
+            };
+            normal.normalize();
+            normals.push(normal);
         } else if line.starts_with("f ") {
             let rest_of_line = &line[2..];
             // most faces are 3 vertices (a triangle), but there is a possibility for a polygon
             let mut coordinate_elements: [usize; 3] = [0; 3];
             let mut texel_elements: [usize; 3] = [0; 3];
+            let mut normal_elements: [usize; 3] = [0; 3];
             for (element_index, element_str) in
-                rest_of_line.split(" ").enumerate()
+                rest_of_line.split_whitespace().enumerate()
             {
+                if element_index >= 3 {
+                    return Err(format!(
+                        "Only triangle faces are supported in {}",
+                        path
+                    ));
+                }
+
                 let vertex_info: Vec<&str> = element_str.split("/").collect();
                 let vertex_index: usize = match vertex_info[0].parse() {
                     Ok(vertex_index) => vertex_index,
-                    Err(_) => panic!("Unable to convert index to usize"),
+                    Err(_) => {
+                        return Err(format!(
+                            "Unable to convert index to usize in {}",
+                            path
+                        ))
+                    }
                 };
-                let texel_index: usize = match vertex_info[1].parse() {
-                    Ok(texel_index) => texel_index,
-                    Err(_) => panic!("Unable to convert texel index to usize"),
+                let texel_index: usize =
+                    match vertex_info.get(1).map(|texel_str| texel_str.parse())
+                    {
+                        Some(Ok(texel_index)) => texel_index,
+                        _ => {
+                            return Err(format!(
+                                "Unable to convert texel index to usize in {}",
+                                path
+                            ))
+                        }
+                    };
+                // The normal index is optional
+                let normal_index: usize = match vertex_info.get(2) {
+                    Some(normal_str) => match normal_str.parse() {
+                        Ok(normal_index) => normal_index,
+                        Err(_) => {
+                            return Err(format!(
+                                "Unable to convert normal index to usize in {}",
+                                path
+                            ))
+                        }
+                    },
+                    None => 0,
                 };
-                // TODO: handle vertex texture and normal info
                 coordinate_elements[element_index] = vertex_index;
                 texel_elements[element_index] = texel_index;
+                normal_elements[element_index] = normal_index;
             }
 
             faces.push(Face {
@@ -114,18 +305,73 @@ pub fn load_obj_mesh(path: &String) -> Mesh {
                 a_uv: texel_elements[0],
                 b_uv: texel_elements[1],
                 c_uv: texel_elements[2],
-                color: 0xFFAAAAAA,
+                a_normal: normal_elements[0],
+                b_normal: normal_elements[1],
+                c_normal: normal_elements[2],
+                material: current_material,
+                color: materials[current_material].diffuse_color.to_argb(),
             });
+        } else if let Some(mtl_file_name) = line.strip_prefix("mtllib ") {
+            // MTL paths are relative to the OBJ file
+            let mtl_path = match Path::new(path).parent() {
+                Some(directory) => directory.join(mtl_file_name.trim()),
+                None => Path::new(mtl_file_name.trim()).to_path_buf(),
+            };
+            let mtl_path = mtl_path.to_string_lossy().to_string();
+            material_library_paths.push(mtl_path.clone());
+
+            match load_mtl_materials(&mtl_path) {
+                Ok(loaded_materials) => {
+                    for (name, material) in loaded_materials {
+                        material_names.push(name);
+                        materials.push(material);
+                    }
+                }
+                Err(error) => {
+                    println!("{}. Using default material", error);
+                }
+            }
+        } else if let Some(material_name) = line.strip_prefix("usemtl ") {
+            current_material = material_names
+                .iter()
+                .position(|name| name == material_name.trim())
+                .unwrap_or(0);
         } else {
             // TODO: don't do anything here yet
         }
     }
 
-    Mesh {
+    // Indices are 1-based. Normal indices may still be 0 (missing) here.
+    for face in &faces {
+        let in_range =
+            |index: usize, count: usize| index >= 1 && index <= count;
+        let normal_in_range =
+            |index: usize| index == 0 || in_range(index, normals.len());
+
+        if !(in_range(face.a, vertices.len())
+            && in_range(face.b, vertices.len())
+            && in_range(face.c, vertices.len())
+            && in_range(face.a_uv, texel_coordinates.len())
+            && in_range(face.b_uv, texel_coordinates.len())
+            && in_range(face.c_uv, texel_coordinates.len())
+            && normal_in_range(face.a_normal)
+            && normal_in_range(face.b_normal)
+            && normal_in_range(face.c_normal))
+        {
+            return Err(format!("Face index out of range in {}", path));
+        }
+    }
+
+    fill_missing_normals(&vertices, &mut normals, &mut faces);
+
+    Ok(Mesh {
         vertices,
         texel_coordinates,
+        normals,
+        materials,
         faces,
-    }
+        material_library_paths,
+    })
 }
 
 const MESH_VERTICES: [Vector3; 8] = [
@@ -187,6 +433,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -196,6 +446,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     // right
@@ -206,6 +460,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -215,6 +473,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     // back
@@ -225,6 +487,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -234,6 +500,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     // left
@@ -244,6 +514,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -253,6 +527,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     // top
@@ -263,6 +541,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -272,6 +554,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     // bottom
@@ -282,6 +568,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 2,
         c_uv: 1,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
     Face {
@@ -291,6 +581,10 @@ const MESH_FACES: [Face; 12] = [
         a_uv: 4,
         b_uv: 1,
         c_uv: 3,
+        a_normal: 0,
+        b_normal: 0,
+        c_normal: 0,
+        material: 0,
         color: 0xFFFFFFFF,
     },
 ];
@@ -0,0 +1,543 @@
+use std::time::Instant;
+
+use crate::{
+    asset_manager::AssetManager,
+    clipping::{clip_triangle, FrustumPlanes},
+    color::Color,
+    depth_pyramid::OcclusionCuller,
+    frame_stats::FrameStats,
+    instance::Instance,
+    light_source::{calc_lighting, Light},
+    material::AlphaMode,
+    matrix::Matrix4,
+    mesh::Mesh,
+    projection::{project_point, project_triangles},
+    shadow::ShadowMap,
+    triangle::{Triangle, Varyings},
+    vector::{calc_cross_product, Vector2, Vector3, Vector4},
+};
+
+const NORMAL_OVERLAY_LENGTH: f32 = 0.2; // length of the face normals drawn by the normal overlay, in world units
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BackfaceCullingMode {
+    Enabled,
+    Disabled,
+}
+
+/// Turns the meshes of instances into screen space triangles. Faces are
+/// transformed to view space, culled, lit, clipped against the view frustum
+/// and projected.
+pub struct GeometryPass<'a> {
+    pub view_matrix: Matrix4,
+    pub projection_matrix: Matrix4,
+    pub frustum_planes: FrustumPlanes,
+    /// Size in pixels of the target the triangles are projected to
+    pub width: u32,
+    pub height: u32,
+    pub culling_mode: BackfaceCullingMode,
+    pub lighting_enabled: bool,
+    /// View space lights
+    pub lights: &'a [Light],
+    pub shadow_maps: &'a [ShadowMap],
+    /// Whether the triangles will be textured. Opaque materials then take on
+    /// the alpha mode of their texture.
+    pub textured: bool,
+    /// When set, replaces the alpha mode of every material
+    pub alpha_mode_override: Option<AlphaMode>,
+    /// Whether to project the normal of each visible face as a line
+    pub normal_overlay_enabled: bool,
+    /// Whether instances hidden behind the occluders are skipped. Every
+    /// instance is needed when hidden surfaces are still drawn, like
+    /// wireframes.
+    pub cull_occluded_instances: bool,
+}
+
+impl GeometryPass<'_> {
+    /// Transforms the meshes of all instances into 'triangles_to_render' and
+    /// the normal overlay into 'normal_lines'. Both are cleared first. The
+    /// counts and timings of each stage are added to 'stats'.
+    ///
+    /// With an occlusion culler, the opaque triangles of each instance are
+    /// drawn into it as occluders. If instances are culled too, they are
+    /// transformed front to back and the ones whose bounding box is hidden
+    /// behind the instances in front of them are skipped.
+    pub fn transform_instances(
+        &self,
+        instances: &[Instance],
+        assets: &AssetManager,
+        mut occlusion_culler: Option<&mut OcclusionCuller>,
+        triangles_to_render: &mut Vec<Triangle>,
+        normal_lines: &mut Vec<(Vector2, Vector2)>,
+        stats: &mut FrameStats,
+    ) {
+        triangles_to_render.clear();
+        normal_lines.clear();
+
+        let mut meshes: Vec<(usize, &Mesh, [Vector4; 8])> = vec![];
+        for (index, instance) in instances.iter().enumerate() {
+            if let Some(mesh) = assets.get_mesh(instance.mesh_handle) {
+                let bounding_box = self.calc_view_bounding_box(instance, mesh);
+                meshes.push((index, mesh, bounding_box));
+            }
+        }
+        stats.instance_count += meshes.len();
+
+        // Nearer instances are more likely to hide the others, so they are
+        // transformed first. The triangles of each instance are kept apart
+        // so that they are still rendered in the order of the instances.
+        let cull_occluded_instances =
+            self.cull_occluded_instances && occlusion_culler.is_some();
+        if cull_occluded_instances {
+            let nearest_z = |corners: &[Vector4; 8]| {
+                corners
+                    .iter()
+                    .map(|corner| corner.z)
+                    .fold(f32::MAX, f32::min)
+            };
+            meshes.sort_by(|a, b| nearest_z(&a.2).total_cmp(&nearest_z(&b.2)));
+        }
+
+        let mut instance_triangles: Vec<Vec<Triangle>> =
+            vec![Vec::new(); instances.len()];
+        for (index, mesh, bounding_box) in meshes {
+            if let Some(occlusion_culler) = occlusion_culler.as_deref_mut() {
+                if cull_occluded_instances
+                    && self.is_bounding_box_occluded(
+                        &bounding_box,
+                        occlusion_culler,
+                    )
+                {
+                    stats.occluded_instance_count += 1;
+                    continue;
+                }
+            }
+
+            self.transform_instance(
+                &instances[index],
+                mesh,
+                assets,
+                &mut instance_triangles[index],
+                normal_lines,
+                stats,
+            );
+
+            if let Some(occlusion_culler) = occlusion_culler.as_deref_mut() {
+                occlusion_culler.draw_occluders(&instance_triangles[index]);
+            }
+        }
+
+        triangles_to_render.extend(instance_triangles.into_iter().flatten());
+    }
+
+    /// Returns the corners of the bounding box of an instance's mesh in view
+    /// space
+    fn calc_view_bounding_box(
+        &self,
+        instance: &Instance,
+        mesh: &Mesh,
+    ) -> [Vector4; 8] {
+        let world_matrix = instance.world_matrix();
+        let (min, max) = mesh.calc_bounding_box();
+
+        std::array::from_fn(|index| {
+            let corner = Vector4 {
+                x: if index & 1 == 0 { min.x } else { max.x },
+                y: if index & 2 == 0 { min.y } else { max.y },
+                z: if index & 4 == 0 { min.z } else { max.z },
+                w: 1.0,
+            };
+            Matrix4::mult_vector(
+                &self.view_matrix,
+                &Matrix4::mult_vector(&world_matrix, &corner),
+            )
+        })
+    }
+
+    /// Returns true if everything inside a view space bounding box is hidden
+    /// behind the occluders drawn so far
+    fn is_bounding_box_occluded(
+        &self,
+        corners: &[Vector4; 8],
+        occlusion_culler: &mut OcclusionCuller,
+    ) -> bool {
+        // Boxes that reach behind the camera can't be projected
+        if corners.iter().any(|corner| corner.z <= 0.0) {
+            return false;
+        }
+
+        let projected_corners = corners.map(|corner| {
+            project_point(
+                &self.projection_matrix,
+                self.width,
+                self.height,
+                &corner,
+            )
+        });
+        match projected_corners.into_iter().collect::<Option<Vec<_>>>() {
+            Some(projected_corners) => {
+                occlusion_culler.is_convex_hull_occluded(&projected_corners)
+            }
+            None => false,
+        }
+    }
+
+    fn transform_instance(
+        &self,
+        instance: &Instance,
+        mesh: &Mesh,
+        assets: &AssetManager,
+        triangles_to_render: &mut Vec<Triangle>,
+        normal_lines: &mut Vec<(Vector2, Vector2)>,
+        stats: &mut FrameStats,
+    ) {
+        // World matrix is invariant for each face
+        let world_matrix = instance.world_matrix();
+
+        // Loop over faces
+        for face in &mesh.faces {
+            let transform_start_time = Instant::now();
+            stats.face_count += 1;
+
+            let vertices: [Vector3; 3] = mesh.get_vertices(face);
+
+            let mut world_vertices: [Vector3; 3] = Default::default();
+            let mut transformed_vertices: [Vector4; 3] = Default::default();
+
+            // Transform
+            for (index, vertex) in vertices.into_iter().enumerate() {
+                // world transform
+                let transformed_vertex = Matrix4::mult_vector(
+                    &world_matrix,
+                    &Vector4::from_vector3(&vertex),
+                );
+                world_vertices[index] =
+                    Vector3::from_vector4(&transformed_vertex);
+
+                // view transform
+                let transformed_vertex = Matrix4::mult_vector(
+                    &self.view_matrix,
+                    &transformed_vertex,
+                );
+                transformed_vertices[index] = transformed_vertex;
+            }
+
+            // Pull out face vectors (left-handed system)
+            /*
+                 A
+                /  \
+                C - B
+            */
+            let vector_a = &Vector3::from_vector4(&transformed_vertices[0]);
+            let vector_b = &Vector3::from_vector4(&transformed_vertices[1]);
+            let vector_c = &Vector3::from_vector4(&transformed_vertices[2]);
+
+            // Find face normal
+            let face_normal: Vector3 = {
+                let ab_vector = {
+                    let mut ab_vector = vector_b - vector_a;
+                    ab_vector.normalize();
+                    ab_vector
+                };
+                let ac_vector = {
+                    let mut ac_vector = vector_c - vector_a;
+                    ac_vector.normalize();
+                    ac_vector
+                };
+                let mut face_normal =
+                    calc_cross_product(&ab_vector, &ac_vector);
+                face_normal.normalize();
+                face_normal
+            };
+
+            // Backface culling
+            let culled: bool =
+                if self.culling_mode == BackfaceCullingMode::Enabled {
+                    // Calculate the to-camera vector.
+                    // Since this is performed after the view matrix transform,
+                    // the camera is at the origin
+                    let face_to_camera = -1.0 * vector_a;
+
+                    let dot_product =
+                        Vector3::dot_product(&face_normal, &face_to_camera);
+
+                    dot_product < 0.0
+                } else {
+                    false
+                };
+
+            if culled {
+                stats.culled_face_count += 1;
+                stats.transform_time += transform_start_time.elapsed();
+                continue;
+            }
+
+            if self.normal_overlay_enabled {
+                let face_center =
+                    (1.0 / 3.0) * &(&(vector_a + vector_b) + vector_c);
+                let normal_end =
+                    &face_center + &(NORMAL_OVERLAY_LENGTH * &face_normal);
+
+                let project = |point: &Vector3| {
+                    project_point(
+                        &self.projection_matrix,
+                        self.width,
+                        self.height,
+                        &Vector4::from_vector3(point),
+                    )
+                };
+                if let (Some(start), Some(end)) =
+                    (project(&face_center), project(&normal_end))
+                {
+                    normal_lines.push((
+                        Vector2::from_vector4(&start),
+                        Vector2::from_vector4(&end),
+                    ));
+                }
+            }
+
+            // Lighting
+            // Flat shading lights the whole face using the light that
+            // reaches its center
+            let light_color: Color = if self.lighting_enabled {
+                let face_center =
+                    (1.0 / 3.0) * &(&(vector_a + vector_b) + vector_c);
+                let world_face_center = (1.0 / 3.0)
+                    * &(&(&world_vertices[0] + &world_vertices[1])
+                        + &world_vertices[2]);
+                let (diffuse, _) = calc_lighting(
+                    self.lights,
+                    self.shadow_maps,
+                    &face_normal,
+                    &face_center,
+                    &world_face_center,
+                    1.0,
+                );
+                diffuse
+            } else {
+                Color::WHITE
+            };
+
+            // Per-vertex attributes for Gouraud and Phong shading. The order
+            // of the pushes must match the varying layout in the render
+            // module.
+            let varyings: [Varyings; 3] = {
+                let vertex_normals = mesh.get_vertex_normals(face);
+
+                let mut varyings: [Varyings; 3] = Default::default();
+                for (index, vertex_normal) in
+                    vertex_normals.into_iter().enumerate()
+                {
+                    // Normals are directions, so they are not affected by
+                    // translation (w = 0)
+                    let vertex_normal = Vector4 {
+                        w: 0.0,
+                        ..Vector4::from_vector3(&vertex_normal)
+                    };
+                    let world_normal =
+                        Matrix4::mult_vector(&world_matrix, &vertex_normal);
+                    let vertex_normal =
+                        Matrix4::mult_vector(&self.view_matrix, &world_normal);
+                    let vertex_normal = Vector3::calc_normalized_vector(
+                        &Vector3::from_vector4(&vertex_normal),
+                    );
+
+                    let vertex_position =
+                        Vector3::from_vector4(&transformed_vertices[index]);
+
+                    let vertex_light_color = if self.lighting_enabled {
+                        let (diffuse, _) = calc_lighting(
+                            self.lights,
+                            self.shadow_maps,
+                            &vertex_normal,
+                            &vertex_position,
+                            &world_vertices[index],
+                            1.0,
+                        );
+                        diffuse
+                    } else {
+                        Color::WHITE
+                    };
+
+                    varyings[index].push_color(&vertex_light_color);
+                    varyings[index].push_vector3(&vertex_normal);
+                    varyings[index].push_vector3(&vertex_position);
+                    varyings[index].push_vector3(&world_vertices[index]);
+                    varyings[index].push_vector3(
+                        &Vector3::calc_normalized_vector(
+                            &Vector3::from_vector4(&world_normal),
+                        ),
+                    );
+                }
+
+                varyings
+            };
+
+            // Opaque materials take on the alpha mode of their texture, so
+            // cutouts and translucent textures work without a transparent
+            // material
+            let mut material = mesh.get_material(face);
+            if self.textured && material.alpha_mode == AlphaMode::Opaque {
+                material.alpha_mode =
+                    assets.get_texture(instance.texture_handle).alpha_mode;
+            }
+            if let Some(alpha_mode) = self.alpha_mode_override {
+                material.alpha_mode = alpha_mode;
+            }
+
+            let triangle = Triangle {
+                points: transformed_vertices,
+                texel_coordinates: mesh.get_texel_coordinates(face),
+                varyings,
+                material,
+                color: face.color,
+                light_color,
+                texture_handle: instance.texture_handle,
+            };
+
+            let clip_start_time = Instant::now();
+            stats.transform_time += clip_start_time - transform_start_time;
+
+            let mut triangles = clip_triangle(&self.frustum_planes, triangle);
+
+            stats.clipped_triangle_count += triangles.len();
+            if triangles.is_empty() {
+                stats.clipped_face_count += 1;
+            }
+
+            let project_start_time = Instant::now();
+            stats.clip_time += project_start_time - clip_start_time;
+
+            project_triangles(
+                &self.projection_matrix,
+                self.width,
+                self.height,
+                &mut triangles,
+                triangles_to_render,
+            );
+
+            stats.project_time += project_start_time.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::{
+        asset_manager::TextureHandle,
+        debug_view::draw_normal_triangle,
+        mesh::load_test_mesh,
+        projection::make_projection_matrix,
+        render::{sort_triangles_for_blending, ColorBuffer, NORMAL_VARYING},
+    };
+
+    const SIZE: usize = 96;
+
+    /// Renders a cube in front of a smaller cube that it hides completely
+    /// and a larger cube that it hides partially. Faces are colored by their
+    /// view space normal, so the turned cubes differ from the others.
+    fn render_scene(
+        occlusion_culling: bool,
+        sample_count: usize,
+    ) -> (ColorBuffer, FrameStats) {
+        let mut assets = AssetManager::new();
+        let mesh_handle = assets.add_mesh(load_test_mesh());
+        let instances = [
+            (0.0, 0.0, 5.0, 2.0, 0.3),
+            (0.0, 0.0, 9.0, 0.5, 0.6),
+            (2.0, -2.0, 10.0, 2.0, 0.0),
+        ]
+        .map(|(x, y, z, scale, angle)| Instance {
+            orientation: Vector4 {
+                x: angle,
+                y: angle,
+                z: 0.0,
+                w: 0.0,
+            },
+            translation: Vector4 { x, y, z, w: 1.0 },
+            scale,
+            mesh_handle,
+            texture_handle: TextureHandle::invalid(),
+        });
+
+        let fov = PI / 3.0;
+        let geometry_pass = GeometryPass {
+            view_matrix: Matrix4::identity(),
+            projection_matrix: make_projection_matrix(fov, 1.0, 0.1, 20.0),
+            frustum_planes: FrustumPlanes::new(0.1, 20.0, fov, fov),
+            width: SIZE as u32,
+            height: SIZE as u32,
+            culling_mode: BackfaceCullingMode::Enabled,
+            lighting_enabled: false,
+            lights: &[],
+            shadow_maps: &[],
+            textured: false,
+            alpha_mode_override: None,
+            normal_overlay_enabled: false,
+            cull_occluded_instances: true,
+        };
+
+        let mut stats = FrameStats::default();
+        let mut occlusion_culler = OcclusionCuller::new(SIZE, SIZE);
+        let mut triangles = vec![];
+        geometry_pass.transform_instances(
+            &instances,
+            &assets,
+            occlusion_culling.then_some(&mut occlusion_culler),
+            &mut triangles,
+            &mut vec![],
+            &mut stats,
+        );
+
+        let mut color_buffer = ColorBuffer::new(SIZE, SIZE);
+        color_buffer.set_sample_count(sample_count);
+        color_buffer.clear(0xFF000000);
+        sort_triangles_for_blending(&mut triangles);
+        if occlusion_culling {
+            occlusion_culler
+                .cull_occluded_triangles(&mut triangles, &mut stats);
+        }
+        for triangle in &triangles {
+            draw_normal_triangle(&mut color_buffer, triangle, NORMAL_VARYING);
+        }
+        color_buffer.resolve_samples();
+        color_buffer.fill_raster_stats(&mut stats);
+
+        (color_buffer, stats)
+    }
+
+    #[test]
+    fn occlusion_culling_keeps_the_image_and_shades_less() {
+        for sample_count in [1, 4] {
+            let (unculled_buffer, unculled_stats) =
+                render_scene(false, sample_count);
+            let (culled_buffer, culled_stats) =
+                render_scene(true, sample_count);
+
+            assert!(
+                culled_buffer.buffer == unculled_buffer.buffer,
+                "Pixels differ with {} samples",
+                sample_count
+            );
+            assert_eq!(unculled_stats.occluded_instance_count, 0);
+            assert_eq!(culled_stats.occluded_instance_count, 1);
+            assert!(culled_stats.occluded_triangle_count > 0);
+            assert!(
+                culled_stats.face_count < unculled_stats.face_count,
+                "{} faces with culling, {} without",
+                culled_stats.face_count,
+                unculled_stats.face_count
+            );
+            assert!(
+                culled_stats.shaded_pixel_count
+                    < unculled_stats.shaded_pixel_count,
+                "{} pixels shaded with culling, {} without",
+                culled_stats.shaded_pixel_count,
+                unculled_stats.shaded_pixel_count
+            );
+        }
+    }
+}
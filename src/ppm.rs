@@ -0,0 +1,173 @@
+use crate::texture::{pack_argb, Texture};
+
+/// Decodes a binary (P5, P6) or ASCII (P2, P3) PGM or PPM image
+pub fn decode_ppm(data: &[u8]) -> Result<Texture, String> {
+    let mut parser = HeaderParser { data, offset: 0 };
+
+    let magic = parser.next_token()?;
+    let (channel_count, is_binary) = match magic {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => {
+            return Err(format!(
+                "Unsupported PPM/PGM type {}",
+                String::from_utf8_lossy(magic)
+            ))
+        }
+    };
+
+    let width = parser.next_number()? as usize;
+    let height = parser.next_number()? as usize;
+    let max_value = parser.next_number()?;
+    if width == 0 || height == 0 {
+        return Err(format!("Bad PPM/PGM dimensions {}x{}", width, height));
+    }
+    if max_value == 0 || max_value > 65535 {
+        return Err(format!("Bad PPM/PGM max value {}", max_value));
+    }
+
+    let sample_count = match width
+        .checked_mul(height)
+        .and_then(|pixel_count| pixel_count.checked_mul(channel_count))
+    {
+        Some(sample_count) => sample_count,
+        None => {
+            return Err(format!(
+                "PPM/PGM dimensions {}x{} are too large",
+                width, height
+            ))
+        }
+    };
+    let mut samples = vec![];
+    if is_binary {
+        // A single whitespace character separates the header from the data.
+        // Samples above 255 take two bytes, most significant first.
+        let mut offset = parser.offset + 1;
+        let bytes_per_sample = if max_value > 255 { 2 } else { 1 };
+        for _ in 0..sample_count {
+            let sample = match data.get(offset..(offset + bytes_per_sample)) {
+                Some([value]) => *value as u32,
+                Some([high, low]) => u16::from_be_bytes([*high, *low]) as u32,
+                _ => return Err("PPM/PGM pixel data is truncated".to_string()),
+            };
+            samples.push(sample);
+            offset += bytes_per_sample;
+        }
+    } else {
+        for _ in 0..sample_count {
+            samples.push(parser.next_number()?);
+        }
+    }
+
+    // Scale samples to 8 bits
+    let to_channel = |sample: u32| {
+        ((sample.min(max_value) * 255 + max_value / 2) / max_value) as u8
+    };
+
+    let argb_data = samples
+        .chunks_exact(channel_count)
+        .map(|pixel| match pixel {
+            [gray] => {
+                let gray = to_channel(*gray);
+                pack_argb(0xFF, gray, gray, gray)
+            }
+            [red, green, blue] => pack_argb(
+                0xFF,
+                to_channel(*red),
+                to_channel(*green),
+                to_channel(*blue),
+            ),
+            _ => unreachable!(),
+        })
+        .collect();
+
+    Ok(Texture::new(width, height, argb_data))
+}
+
+/// Splits the whitespace separated tokens of a PPM/PGM header, skipping
+/// comments
+struct HeaderParser<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> HeaderParser<'a> {
+    fn next_token(&mut self) -> Result<&'a [u8], String> {
+        // Skip whitespace and comments, which run from '#' to the end of the
+        // line
+        while let Some(byte) = self.data.get(self.offset) {
+            if byte.is_ascii_whitespace() {
+                self.offset += 1;
+            } else if *byte == b'#' {
+                while let Some(byte) = self.data.get(self.offset) {
+                    if *byte == b'\n' {
+                        break;
+                    }
+                    self.offset += 1;
+                }
+            } else {
+                break;
+            }
+        }
+
+        let start = self.offset;
+        while let Some(byte) = self.data.get(self.offset) {
+            if byte.is_ascii_whitespace() {
+                break;
+            }
+            self.offset += 1;
+        }
+
+        if start == self.offset {
+            Err("PPM/PGM data is truncated".to_string())
+        } else {
+            Ok(&self.data[start..self.offset])
+        }
+    }
+
+    fn next_number(&mut self) -> Result<u32, String> {
+        let token = self.next_token()?;
+        match std::str::from_utf8(token).ok().and_then(|s| s.parse().ok()) {
+            Some(number) => Ok(number),
+            None => Err(format!(
+                "Bad number in PPM/PGM data: {}",
+                String::from_utf8_lossy(token)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_ascii_ppm() {
+        let texture =
+            decode_ppm(b"P3\n# comment\n2 1\n255\n255 0 0 0 128 255\n")
+                .unwrap();
+        assert_eq!(texture.levels[0].data, [0xFFFF0000, 0xFF0080FF]);
+    }
+
+    #[test]
+    fn decode_binary_pgm_with_16_bit_samples() {
+        let mut data = b"P5 2 1 65535\n".to_vec();
+        data.extend_from_slice(&[0xFF, 0xFF, 0x80, 0x00]);
+        let texture = decode_ppm(&data).unwrap();
+        assert_eq!(texture.levels[0].data, [0xFFFFFFFF, 0xFF808080]);
+    }
+
+    #[test]
+    fn decode_ppm_rejects_zero_dimensions() {
+        assert!(decode_ppm(b"P6 0 0 255\n").is_err());
+        assert!(decode_ppm(b"P5 0 4 255\n").is_err());
+        assert!(decode_ppm(b"P2 4 0 255\n").is_err());
+    }
+
+    #[test]
+    fn decode_ppm_rejects_overflowing_dimensions() {
+        assert!(decode_ppm(b"P6 4294967295 4294967295 255\n").is_err());
+    }
+}
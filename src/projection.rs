@@ -17,6 +17,25 @@ pub fn make_projection_matrix(
     result
 }
 
+/// Makes a projection matrix for parallel projection of a box of the given
+/// half-extents. Points keep w = 1, so the perspective divide has no effect.
+pub fn make_orthographic_matrix(
+    half_width: f32,
+    half_height: f32,
+    znear: f32,
+    zfar: f32,
+) -> Matrix4 {
+    let mut result = Matrix4::zero();
+
+    result.data[0][0] = 1.0 / half_width;
+    result.data[1][1] = 1.0 / half_height;
+    result.data[2][2] = 1.0 / (zfar - znear);
+    result.data[2][3] = (-1.0 * znear) / (zfar - znear);
+    result.data[3][3] = 1.0;
+
+    result
+}
+
 fn perspective_projection(
     projection_matrix: &Matrix4,
     vector: &Vector4,
@@ -41,23 +60,13 @@ pub fn project_triangles(
 ) {
     for triangle in triangles {
         for vertex in &mut triangle.points {
-            match perspective_projection(&projection_matrix, vertex) {
+            match project_point(
+                projection_matrix,
+                window_width,
+                window_height,
+                vertex,
+            ) {
                 Some(projected_point) => {
-                    let mut projected_point = projected_point.clone();
-                    // perform windowing transform (scale then translate)
-                    // the division by 2 is b/c we are mapping the canonical view volume (which has bounds x,y: [-1, 1]) to screen
-                    // space (which has bounds x: [0, window_width], y: [0, window_height])
-                    {
-                        projected_point.x *= window_width as f32 / 2.0;
-                        projected_point.y *= window_height as f32 / 2.0;
-
-                        // since y grows down in screen space, but up in world space / canonical image space
-                        projected_point.y *= -1.0;
-
-                        projected_point.x += window_width as f32 / 2.0;
-                        projected_point.y += window_height as f32 / 2.0;
-                    }
-
                     *vertex = projected_point;
                 }
                 None => {}
@@ -66,3 +75,30 @@ pub fn project_triangles(
         triangles_to_render.push(triangle.clone());
     }
 }
+
+/// Projects a single point and maps it to window coordinates
+pub fn project_point(
+    projection_matrix: &Matrix4,
+    window_width: u32,
+    window_height: u32,
+    vector: &Vector4,
+) -> Option<Vector4> {
+    let mut projected_point =
+        perspective_projection(projection_matrix, vector)?;
+
+    // perform windowing transform (scale then translate)
+    // the division by 2 is b/c we are mapping the canonical view volume (which has bounds x,y: [-1, 1]) to screen
+    // space (which has bounds x: [0, window_width], y: [0, window_height])
+    {
+        projected_point.x *= window_width as f32 / 2.0;
+        projected_point.y *= window_height as f32 / 2.0;
+
+        // since y grows down in screen space, but up in world space / canonical image space
+        projected_point.y *= -1.0;
+
+        projected_point.x += window_width as f32 / 2.0;
+        projected_point.y += window_height as f32 / 2.0;
+    }
+
+    Some(projected_point)
+}
@@ -1,16 +1,190 @@
+use std::cmp::Ordering;
+
 use crate::{
-    light_source::apply_intensity,
+    color::Color,
+    font::{get_glyph, CHARACTER_ADVANCE, GLYPH_HEIGHT, LINE_ADVANCE},
+    frame_stats::FrameStats,
+    light_source::{apply_lighting, calc_lighting, Light},
+    line::{
+        draw_depth_tested_line, draw_styled_line, HiddenLineMode, LineStyle,
+    },
+    material::{AlphaMode, Material},
     matrix::Matrix4,
-    texture::{Texture, TextureUv},
-    triangle::{get_sorted_triangle_vertices, Triangle},
-    vector::{Vector2, Vector2i, Vector4},
+    shadow::ShadowMap,
+    texture::{Sampler, Texture, TextureUv},
+    triangle::{
+        get_sorted_triangle_vertices, Triangle, TriangleVertex, Varyings,
+    },
+    vector::{Vector2, Vector2i, Vector3, Vector4},
 };
 
+// Layout of the varyings used by the built-in shading modes
+pub const LIGHT_VARYING: usize = 0;
+pub const NORMAL_VARYING: usize = 3;
+pub const POSITION_VARYING: usize = 6;
+pub const WORLD_POSITION_VARYING: usize = 9;
+pub const WORLD_NORMAL_VARYING: usize = 12;
+
 pub struct ColorBuffer {
     pub buffer: Vec<u32>,
     pub zbuffer: Vec<f32>,
     pub width: usize,
     pub height: usize,
+    /// When set, blended fragments are collected here and composited in
+    /// depth order by resolve_transparency rather than blended immediately
+    pub a_buffer: Option<ABuffer>,
+    /// When set, triangles are rasterized into several samples per pixel
+    /// that are averaged into 'buffer' by resolve_samples
+    multisample: Option<MultisampleBuffer>,
+    /// When set, the number of fragments drawn to each pixel since the buffer
+    /// was cleared, whether or not they passed the depth test
+    pub overdraw: Option<Vec<u32>>,
+    /// Number of triangles rasterized with shading since the buffer was
+    /// cleared
+    triangle_count: usize,
+    /// Number of fragments shaded since the buffer was cleared
+    fragment_count: usize,
+}
+
+/// The maximum number of samples per pixel for multisampling
+pub const MAX_SAMPLES: usize = 8;
+
+// Sample positions relative to a pixel's position, in pixels. These are the
+// standard rotated grid patterns, which sample more distinct x and y values
+// than an ordered grid with the same number of samples.
+const SAMPLE_PATTERN_2X: [(f32, f32); 2] = [(0.25, 0.25), (-0.25, -0.25)];
+const SAMPLE_PATTERN_4X: [(f32, f32); 4] = [
+    (-0.125, -0.375),
+    (0.375, -0.125),
+    (-0.375, 0.125),
+    (0.125, 0.375),
+];
+const SAMPLE_PATTERN_8X: [(f32, f32); 8] = [
+    (0.0625, -0.1875),
+    (-0.0625, 0.1875),
+    (0.3125, 0.0625),
+    (-0.1875, -0.3125),
+    (-0.3125, 0.3125),
+    (-0.4375, -0.0625),
+    (0.1875, 0.4375),
+    (0.4375, -0.4375),
+];
+
+/// Per-sample color and depth for multisample anti-aliasing. Coverage and
+/// depth are tested for every sample, but each pixel is only shaded once per
+/// triangle.
+struct MultisampleBuffer {
+    sample_offsets: &'static [(f32, f32)],
+    /// sample_offsets.len() samples for each pixel
+    colors: Vec<u32>,
+    reciprocal_zs: Vec<f32>,
+    /// Whether any sample was drawn since the buffer was cleared
+    written: bool,
+}
+
+/// A blended fragment waiting to be composited
+#[derive(Clone, Copy, Default)]
+struct TransparentFragment {
+    color: u32,
+    reciprocal_z: f32,
+}
+
+/// Collects a bounded list of transparent fragments for every pixel so that
+/// they can be composited in depth order regardless of the order triangles
+/// are drawn in. This handles intersecting transparent triangles, which
+/// sorting whole triangles can't.
+pub struct ABuffer {
+    /// Maximum number of fragments stored for each pixel
+    max_fragments: usize,
+    /// max_fragments slots for each pixel
+    fragments: Vec<TransparentFragment>,
+    fragment_counts: Vec<usize>,
+}
+
+impl ABuffer {
+    pub fn new(width: usize, height: usize, max_fragments: usize) -> Self {
+        let max_fragments = max_fragments.max(1);
+        Self {
+            max_fragments,
+            fragments: vec![
+                TransparentFragment::default();
+                width * height * max_fragments
+            ],
+            fragment_counts: vec![0; width * height],
+        }
+    }
+
+    /// Adds a fragment to a pixel's list. When the list is full the two
+    /// farthest fragments are merged by compositing the nearer one over the
+    /// farther one. This keeps the nearest fragments, which contribute the
+    /// most to the final color, exact.
+    fn add_fragment(&mut self, index: usize, fragment: TransparentFragment) {
+        let start = index * self.max_fragments;
+        let count = self.fragment_counts[index];
+        let fragments =
+            &mut self.fragments[start..(start + self.max_fragments)];
+
+        if count < self.max_fragments {
+            fragments[count] = fragment;
+            self.fragment_counts[index] += 1;
+            return;
+        }
+
+        // Find the farthest stored fragment (the smallest reciprocal z)
+        let mut farthest = 0;
+        for (slot, stored_fragment) in fragments.iter().enumerate() {
+            if stored_fragment.reciprocal_z < fragments[farthest].reciprocal_z {
+                farthest = slot;
+            }
+        }
+
+        // If the new fragment is the farthest, it is merged with the farthest
+        // stored fragment. Otherwise the new fragment takes the farthest
+        // fragment's slot, and the farthest fragment is merged with the
+        // second farthest.
+        let (far_fragment, merge_slot) =
+            if fragment.reciprocal_z <= fragments[farthest].reciprocal_z {
+                (fragment, farthest)
+            } else {
+                let far_fragment = fragments[farthest];
+                fragments[farthest] = fragment;
+
+                let mut second_farthest = 0;
+                for (slot, stored_fragment) in fragments.iter().enumerate() {
+                    if stored_fragment.reciprocal_z
+                        < fragments[second_farthest].reciprocal_z
+                    {
+                        second_farthest = slot;
+                    }
+                }
+                (far_fragment, second_farthest)
+            };
+
+        let near_fragment = fragments[merge_slot];
+        fragments[merge_slot] = TransparentFragment {
+            color: composite_over(near_fragment.color, far_fragment.color),
+            reciprocal_z: near_fragment.reciprocal_z,
+        };
+    }
+}
+
+/// Composites a color over another color (source-over). The result's alpha
+/// is the combined coverage of both colors.
+pub fn composite_over(source: u32, destination: u32) -> u32 {
+    let source = Color::from_argb(source);
+    let destination = Color::from_argb(destination);
+
+    let alpha = source.a + destination.a * (1.0 - source.a);
+    if alpha <= 0.0 {
+        return 0;
+    }
+
+    // Colors are weighted by their alpha, then divided by the combined alpha
+    // so that the result can be composited again
+    let blended = &(source.a * &source)
+        + &((destination.a * (1.0 - source.a)) * &destination);
+
+    ((1.0 / alpha) * &blended).with_alpha(alpha).to_argb()
 }
 
 impl ColorBuffer {
@@ -21,6 +195,227 @@ impl ColorBuffer {
             zbuffer: vec![0.0; width * height],
             width,
             height,
+            a_buffer: None,
+            multisample: None,
+            overdraw: None,
+            triangle_count: 0,
+            fragment_count: 0,
+        }
+    }
+
+    /// Enables or disables counting the fragments drawn to each pixel. Does
+    /// nothing if counting is already in the requested state.
+    pub fn set_overdraw_enabled(&mut self, enabled: bool) {
+        if enabled && self.overdraw.is_none() {
+            self.overdraw = Some(vec![0; self.width * self.height]);
+        } else if !enabled {
+            self.overdraw = None;
+        }
+    }
+
+    /// Counts a fragment drawn to a pixel if overdraw counting is enabled
+    pub fn count_fragment(&mut self, x: usize, y: usize) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        if let Some(overdraw) = &mut self.overdraw {
+            overdraw[y * self.width + x] += 1;
+        }
+    }
+
+    /// Sets the number of samples stored for each pixel. 2, 4 and 8 enable
+    /// multisampling; any other count disables it.
+    pub fn set_sample_count(&mut self, sample_count: usize) {
+        let sample_offsets: &'static [(f32, f32)] = match sample_count {
+            2 => &SAMPLE_PATTERN_2X,
+            4 => &SAMPLE_PATTERN_4X,
+            8 => &SAMPLE_PATTERN_8X,
+            _ => {
+                self.multisample = None;
+                return;
+            }
+        };
+
+        let size = self.width * self.height * sample_offsets.len();
+        self.multisample = Some(MultisampleBuffer {
+            sample_offsets,
+            colors: vec![0; size],
+            reciprocal_zs: vec![0.0; size],
+            written: false,
+        });
+    }
+
+    /// Writes an opaque fragment to every sample it covers that passes the
+    /// depth test
+    pub fn set_fragment(&mut self, fragment: &Fragment, color: u32) {
+        let x = fragment.x as usize;
+        let y = fragment.y as usize;
+
+        let multisample = match &mut self.multisample {
+            Some(multisample) => multisample,
+            None => {
+                self.set_pixel_zcell(x, y, fragment.reciprocal_w, color);
+                return;
+            }
+        };
+
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        let sample_count = multisample.sample_offsets.len();
+        let first_sample = (y * self.width + x) * sample_count;
+        for sample in 0..sample_count {
+            if fragment.coverage & (1 << sample) == 0 {
+                continue;
+            }
+
+            let index = first_sample + sample;
+            let reciprocal_z = fragment.sample_reciprocal_ws[sample];
+            if multisample.reciprocal_zs[index] < reciprocal_z {
+                multisample.colors[index] = color;
+                multisample.reciprocal_zs[index] = reciprocal_z;
+                multisample.written = true;
+            }
+        }
+    }
+
+    /// Composites a fragment over every sample it covers that passes the
+    /// depth test. When the A-buffer is enabled the fragment is stored there
+    /// for the whole pixel instead.
+    pub fn blend_fragment(&mut self, fragment: &Fragment, color: u32) {
+        let x = fragment.x as usize;
+        let y = fragment.y as usize;
+
+        let multisample = match &mut self.multisample {
+            Some(multisample) if self.a_buffer.is_none() => multisample,
+            _ => {
+                self.blend_pixel_zcell(x, y, fragment.reciprocal_w, color);
+                return;
+            }
+        };
+
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        let sample_count = multisample.sample_offsets.len();
+        let first_sample = (y * self.width + x) * sample_count;
+        for sample in 0..sample_count {
+            if fragment.coverage & (1 << sample) == 0 {
+                continue;
+            }
+
+            let index = first_sample + sample;
+            if multisample.reciprocal_zs[index]
+                < fragment.sample_reciprocal_ws[sample]
+            {
+                multisample.colors[index] =
+                    composite_over(color, multisample.colors[index]);
+                multisample.written = true;
+            }
+        }
+    }
+
+    /// Averages the samples of each pixel into the color buffer. The zbuffer
+    /// receives the nearest sample depth of each pixel. Does nothing if the
+    /// buffer isn't multisampled or nothing was drawn into the samples.
+    pub fn resolve_samples(&mut self) {
+        let multisample = match &mut self.multisample {
+            Some(multisample) if multisample.written => multisample,
+            _ => return,
+        };
+
+        let sample_count = multisample.sample_offsets.len();
+        for index in 0..(self.width * self.height) {
+            let first_sample = index * sample_count;
+            let samples = first_sample..(first_sample + sample_count);
+
+            let mut color_sum = Color::default();
+            let mut nearest_reciprocal_z: f32 = 0.0;
+            for sample in samples {
+                color_sum =
+                    &color_sum + &Color::from_argb(multisample.colors[sample]);
+                nearest_reciprocal_z =
+                    nearest_reciprocal_z.max(multisample.reciprocal_zs[sample]);
+            }
+
+            self.buffer[index] =
+                ((1.0 / sample_count as f32) * &color_sum).to_argb();
+            self.zbuffer[index] = nearest_reciprocal_z;
+        }
+    }
+
+    /// Box filters this buffer down into a smaller buffer, for supersampling.
+    /// Each pixel of the target is the average of the block of pixels it
+    /// covers in this buffer. The target's zbuffer receives the nearest depth
+    /// in each block.
+    pub fn downsample_into(&self, target: &mut ColorBuffer) {
+        let x_factor = (self.width / target.width).max(1);
+        let y_factor = (self.height / target.height).max(1);
+        let block_size = (x_factor * y_factor) as f32;
+
+        for y in 0..target.height {
+            for x in 0..target.width {
+                let mut color_sum = Color::default();
+                let mut nearest_reciprocal_z: f32 = 0.0;
+                for source_y in (y * y_factor)..((y + 1) * y_factor) {
+                    for source_x in (x * x_factor)..((x + 1) * x_factor) {
+                        let source_index = source_y * self.width + source_x;
+                        color_sum = &color_sum
+                            + &Color::from_argb(self.buffer[source_index]);
+                        nearest_reciprocal_z = nearest_reciprocal_z
+                            .max(self.zbuffer[source_index]);
+                    }
+                }
+
+                let index = y * target.width + x;
+                target.buffer[index] =
+                    ((1.0 / block_size) * &color_sum).to_argb();
+                target.zbuffer[index] = nearest_reciprocal_z;
+            }
+        }
+    }
+
+    /// Draws text with the built-in bitmap font. (x, y) is the top left corner
+    /// of the first character. Each pixel of the font is drawn as a square of
+    /// 'scale' pixels. Lines are separated by '\n'.
+    pub fn draw_text(
+        &mut self,
+        x: i32,
+        y: i32,
+        text: &str,
+        color: u32,
+        scale: usize,
+    ) {
+        let scale = scale.max(1) as i32;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line_y = y + line_index as i32 * LINE_ADVANCE as i32 * scale;
+
+            for (character_index, character) in line.chars().enumerate() {
+                let character_x = x + character_index as i32
+                    * CHARACTER_ADVANCE as i32
+                    * scale;
+
+                for (column, bits) in get_glyph(character).iter().enumerate() {
+                    for row in 0..GLYPH_HEIGHT {
+                        if bits & (1 << row) == 0 {
+                            continue;
+                        }
+
+                        draw_rect(
+                            self,
+                            character_x + column as i32 * scale,
+                            line_y + row as i32 * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
         }
     }
 
@@ -59,6 +454,105 @@ impl ColorBuffer {
         }
     }
 
+    /// Writes a depth to the zbuffer if it passes the depth test
+    pub fn set_zcell(&mut self, x: usize, y: usize, reciprocal_z: f32) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        let index = y * self.width + x;
+        if self.zbuffer[index] < reciprocal_z {
+            self.zbuffer[index] = reciprocal_z;
+        }
+    }
+
+    /// Composites a color over a single pixel (source-over) if it passes the
+    /// depth test. The zbuffer is not updated, so surfaces behind a blended
+    /// surface are still drawn. If the A-buffer is enabled the color is
+    /// stored there instead.
+    pub fn blend_pixel_zcell(
+        &mut self,
+        x: usize,
+        y: usize,
+        reciprocal_z: f32,
+        color: u32,
+    ) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+
+        let index = y * self.width + x;
+
+        if self.zbuffer[index] < reciprocal_z {
+            match &mut self.a_buffer {
+                Some(a_buffer) => a_buffer.add_fragment(
+                    index,
+                    TransparentFragment {
+                        color,
+                        reciprocal_z,
+                    },
+                ),
+                None => {
+                    self.buffer[index] =
+                        composite_over(color, self.buffer[index])
+                }
+            }
+        }
+    }
+
+    /// Enables or disables the A-buffer. The A-buffer stores up to
+    /// 'max_fragments' transparent fragments per pixel.
+    pub fn set_a_buffer_enabled(
+        &mut self,
+        enabled: bool,
+        max_fragments: usize,
+    ) {
+        self.a_buffer = if enabled {
+            Some(ABuffer::new(self.width, self.height, max_fragments))
+        } else {
+            None
+        };
+    }
+
+    /// Composites the fragments collected in the A-buffer from back to front
+    /// and empties it. Fragments that ended up behind an opaque surface drawn
+    /// after them are dropped. Does nothing if the A-buffer is disabled.
+    pub fn resolve_transparency(&mut self) {
+        let a_buffer = match &mut self.a_buffer {
+            Some(a_buffer) => a_buffer,
+            None => return,
+        };
+
+        for index in 0..(self.width * self.height) {
+            let count = a_buffer.fragment_counts[index];
+            if count == 0 {
+                continue;
+            }
+
+            let start = index * a_buffer.max_fragments;
+            let fragments = &mut a_buffer.fragments[start..(start + count)];
+
+            // Farthest (smallest reciprocal z) first
+            fragments.sort_by(|a, b| a.reciprocal_z.total_cmp(&b.reciprocal_z));
+
+            for fragment in fragments.iter() {
+                if fragment.reciprocal_z > self.zbuffer[index] {
+                    self.buffer[index] =
+                        composite_over(fragment.color, self.buffer[index]);
+                }
+            }
+
+            a_buffer.fragment_counts[index] = 0;
+        }
+    }
+
+    /// Fills in the triangles and fragments rasterized into the buffer since
+    /// it was cleared
+    pub fn fill_raster_stats(&self, stats: &mut FrameStats) {
+        stats.rasterized_triangle_count = self.triangle_count;
+        stats.shaded_pixel_count = self.fragment_count;
+    }
+
     /// Clears the color buffer to a specified color and clears zbuffer to 1.0.
     /// The zbuffer is cleared to 1.0 because the z coordinates are normalized
     /// and stored as a reciprocal.
@@ -69,6 +563,18 @@ impl ColorBuffer {
             let zcell = self.zbuffer.get_mut(index).unwrap();
             *zcell = 0.0;
         }
+
+        if let Some(overdraw) = &mut self.overdraw {
+            overdraw.fill(0);
+        }
+        self.triangle_count = 0;
+        self.fragment_count = 0;
+
+        if let Some(multisample) = &mut self.multisample {
+            multisample.colors.fill(color);
+            multisample.reciprocal_zs.fill(0.0);
+            multisample.written = false;
+        }
     }
 }
 
@@ -82,7 +588,9 @@ pub fn draw_dot_grid(color_buffer: &mut ColorBuffer, step: usize, color: u32) {
     }
 }
 
-pub fn draw_rect(
+/// Composites a color over a rectangle of pixels (source-over), using the
+/// color's alpha
+pub fn blend_rect(
     color_buffer: &mut ColorBuffer,
     x: i32,
     y: i32,
@@ -90,42 +598,27 @@ pub fn draw_rect(
     height: i32,
     color: u32,
 ) {
-    for y in y..(y + height) {
-        for x in x..(x + width) {
-            color_buffer.set_pixel(x as usize, y as usize, color);
+    for y in y.max(0)..(y + height).min(color_buffer.height as i32) {
+        for x in x.max(0)..(x + width).min(color_buffer.width as i32) {
+            let index = y as usize * color_buffer.width + x as usize;
+            color_buffer.buffer[index] =
+                composite_over(color, color_buffer.buffer[index]);
         }
     }
 }
 
-pub fn draw_line(
+pub fn draw_rect(
     color_buffer: &mut ColorBuffer,
-    x_1: i32,
-    y_1: i32,
-    x_2: i32,
-    y_2: i32,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
     color: u32,
 ) {
-    let delta_x = x_2 - x_1;
-    let delta_y = y_2 - y_1;
-
-    let side_length = {
-        if delta_x.abs() > delta_y.abs() {
-            delta_x.abs()
-        } else {
-            delta_y.abs()
+    for y in y..(y + height) {
+        for x in x..(x + width) {
+            color_buffer.set_pixel(x as usize, y as usize, color);
         }
-    };
-
-    let x_increment = delta_x as f64 / side_length as f64;
-    let y_increment = delta_y as f64 / side_length as f64;
-
-    let mut x = x_1 as f64;
-    let mut y = y_1 as f64;
-    for _ in 0..side_length {
-        x += x_increment;
-        y += y_increment;
-
-        color_buffer.set_pixel(x as usize, y as usize, color);
     }
 }
 
@@ -143,40 +636,54 @@ pub fn draw_triangle_vertices(
     draw_rect(color_buffer, point_2.x, point_2.y, 5, 5, color);
 }
 
+/// Draws the edges of a triangle. If 'hidden_line_mode' is set, the edges
+/// are depth tested against the zbuffer and hidden parts are drawn according
+/// to it.
 pub fn draw_triangle(
     color_buffer: &mut ColorBuffer,
     triangle: &Triangle,
     color: u32,
+    style: &LineStyle,
+    hidden_line_mode: Option<HiddenLineMode>,
 ) {
-    let point_0 = Vector2i::from_vector4(&triangle.points[0]);
-    let point_1 = Vector2i::from_vector4(&triangle.points[1]);
-    let point_2 = Vector2i::from_vector4(&triangle.points[2]);
+    for index in 0..3 {
+        let start = &triangle.points[index];
+        let end = &triangle.points[(index + 1) % 3];
+
+        match hidden_line_mode {
+            Some(hidden_line_mode) => draw_depth_tested_line(
+                color_buffer,
+                start,
+                end,
+                color,
+                style,
+                hidden_line_mode,
+            ),
+            None => draw_styled_line(
+                color_buffer,
+                &Vector2::from_vector4(start),
+                &Vector2::from_vector4(end),
+                color,
+                style,
+            ),
+        }
+    }
+}
 
-    // draw lines of triangle
-    draw_line(
-        color_buffer,
-        point_0.x,
-        point_0.y,
-        point_1.x,
-        point_1.y,
-        color,
-    );
-    draw_line(
-        color_buffer,
-        point_1.x,
-        point_1.y,
-        point_2.x,
-        point_2.y,
-        color,
-    );
-    draw_line(
-        color_buffer,
-        point_2.x,
-        point_2.y,
-        point_0.x,
-        point_0.y,
-        color,
-    );
+/// Draws a triangle into the zbuffer only, leaving the colors untouched.
+/// Used as a depth prepass for depth tested lines.
+pub fn draw_depth_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+) {
+    rasterize_triangle(triangle, |fragment| {
+        color_buffer.count_fragment(fragment.x as usize, fragment.y as usize);
+        color_buffer.set_zcell(
+            fragment.x as usize,
+            fragment.y as usize,
+            fragment.reciprocal_w,
+        );
+    });
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -214,7 +721,19 @@ fn draw_flat_bottom_triangle(
         let x_start = x_start_f as i32;
         let x_end = x_end_f as i32;
 
-        draw_line(color_buffer, x_start, y, x_end, y, color);
+        draw_styled_line(
+            color_buffer,
+            &Vector2 {
+                x: x_start as f32,
+                y: y as f32,
+            },
+            &Vector2 {
+                x: x_end as f32,
+                y: y as f32,
+            },
+            color,
+            &LineStyle::default(),
+        );
 
         x_start_f += inv_slope_1;
         x_end_f += inv_slope_2;
@@ -253,21 +772,313 @@ fn draw_flat_top_triangle(
         let x_start = x_start_f as i32;
         let x_end = x_end_f as i32;
 
-        draw_line(color_buffer, x_start, y, x_end, y, color);
+        draw_styled_line(
+            color_buffer,
+            &Vector2 {
+                x: x_start as f32,
+                y: y as f32,
+            },
+            &Vector2 {
+                x: x_end as f32,
+                y: y as f32,
+            },
+            color,
+            &LineStyle::default(),
+        );
         // y is decreasing, so we subtract
         x_start_f -= inv_slope_1;
         x_end_f -= inv_slope_2;
     }
 }
 
+/// The interpolated values of a single pixel covered by a triangle
+pub struct Fragment {
+    pub x: i32,
+    pub y: i32,
+    pub reciprocal_w: f32,
+    pub uv: TextureUv,
+    /// Change in UV per pixel along the screen's x axis
+    pub uv_dx: TextureUv,
+    /// Change in UV per pixel along the screen's y axis
+    pub uv_dy: TextureUv,
+    pub varyings: Varyings,
+    /// Bit i is set if the triangle covers sample i of the pixel. Fragments
+    /// that aren't multisampled only cover sample 0.
+    pub coverage: u32,
+    /// Reciprocal w at each covered sample, used for per-sample depth tests
+    pub sample_reciprocal_ws: [f32; MAX_SAMPLES],
+}
+
+/// Writes a shaded fragment to the color buffer according to an alpha mode
+fn write_fragment(
+    color_buffer: &mut ColorBuffer,
+    fragment: &Fragment,
+    color: u32,
+    alpha_mode: &AlphaMode,
+) {
+    match alpha_mode {
+        AlphaMode::Opaque => {
+            color_buffer.set_fragment(fragment, color | 0xFF000000)
+        }
+        AlphaMode::Test { cutoff } => {
+            let alpha = (color >> 24) as f32 / 255.0;
+            if alpha >= *cutoff {
+                color_buffer.set_fragment(fragment, color | 0xFF000000);
+            }
+        }
+        AlphaMode::Blend => color_buffer.blend_fragment(fragment, color),
+    }
+}
+
+/// Rasterizes a triangle into a color buffer, using the buffer's multisample
+/// pattern if it has one. The triangle and its fragments are counted towards
+/// the buffer's statistics.
+fn rasterize_into<F>(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    mut fragment_fn: F,
+) where
+    F: FnMut(&mut ColorBuffer, &Fragment),
+{
+    color_buffer.triangle_count += 1;
+
+    let mut fragment_fn = |color_buffer: &mut ColorBuffer,
+                           fragment: &Fragment| {
+        color_buffer.fragment_count += 1;
+        color_buffer.count_fragment(fragment.x as usize, fragment.y as usize);
+        fragment_fn(color_buffer, fragment);
+    };
+
+    let sample_offsets = color_buffer
+        .multisample
+        .as_ref()
+        .map(|multisample| multisample.sample_offsets);
+
+    match sample_offsets {
+        Some(sample_offsets) => {
+            rasterize_triangle_multisampled(
+                triangle,
+                sample_offsets,
+                |fragment| fragment_fn(color_buffer, fragment),
+            );
+        }
+        None => rasterize_triangle(triangle, |fragment| {
+            fragment_fn(color_buffer, fragment)
+        }),
+    }
+}
+
+/// Orders triangles so that opaque triangles are drawn first, followed by
+/// blended triangles from back to front. Blending is order dependent, so each
+/// blended triangle has to be composited over everything behind it.
+pub fn sort_triangles_for_blending(triangles: &mut [Triangle]) {
+    let is_blended =
+        |triangle: &Triangle| triangle.material.alpha_mode == AlphaMode::Blend;
+    // w is the view space depth of a projected point
+    let calc_depth = |triangle: &Triangle| {
+        triangle.points.iter().map(|point| point.w).sum::<f32>()
+    };
+
+    // The sort is stable, so opaque triangles keep their order
+    triangles.sort_by(|a, b| match (is_blended(a), is_blended(b)) {
+        (false, false) => Ordering::Equal,
+        (false, true) => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (true, true) => calc_depth(b).total_cmp(&calc_depth(a)),
+    });
+}
+
+/// Samples a texture at a fragment's UV, using its UV derivatives to choose a
+/// mip level. The texture is wrapped according to the material, and its alpha
+/// is scaled by the material's opacity.
+fn sample_texture(
+    sampler: &Sampler,
+    texture: &Texture,
+    material: &Material,
+    fragment: &Fragment,
+) -> u32 {
+    let sampler = Sampler {
+        wrap_u: material.wrap_u,
+        wrap_v: material.wrap_v,
+        ..*sampler
+    };
+    let color =
+        sampler.sample(texture, &fragment.uv, &fragment.uv_dx, &fragment.uv_dy);
+
+    let opacity = material.diffuse_color.a;
+    if opacity >= 1.0 {
+        return color;
+    }
+    let color = Color::from_argb(color);
+    color.with_alpha(color.a * opacity).to_argb()
+}
+
+/// Draws a triangle with a single color that is depth tested against the
+/// zbuffer
 pub fn draw_filled_triangle(
     color_buffer: &mut ColorBuffer,
     triangle: &Triangle,
     color: u32,
 ) {
     // Perform flat shading
-    let color = apply_intensity(color, triangle.light_intensity);
+    let color = (Color::from_argb(color) * triangle.light_color).to_argb();
+
+    rasterize_into(color_buffer, triangle, |color_buffer, fragment| {
+        write_fragment(
+            color_buffer,
+            fragment,
+            color,
+            &triangle.material.alpha_mode,
+        );
+    });
+}
+
+/// Draws a textured triangle that is depth tested against the zbuffer
+pub fn draw_textured_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    texture: &Texture,
+    sampler: &Sampler,
+) {
+    rasterize_into(color_buffer, triangle, |color_buffer, fragment| {
+        let pixel_color = {
+            let pixel_color =
+                sample_texture(sampler, texture, &triangle.material, fragment);
+            (Color::from_argb(pixel_color) * triangle.light_color).to_argb()
+        };
+        write_fragment(
+            color_buffer,
+            fragment,
+            pixel_color,
+            &triangle.material.alpha_mode,
+        );
+    });
+}
+
+/// Draws a triangle with a single color that is Gouraud shaded. The light
+/// color at each vertex is expected at LIGHT_VARYING.
+pub fn draw_gouraud_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    color: u32,
+) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        let light_color = fragment.varyings.get_color(LIGHT_VARYING);
+        (Color::from_argb(color) * light_color).to_argb()
+    });
+}
+
+/// Draws a textured triangle that is Gouraud shaded. The light color at each
+/// vertex is expected at LIGHT_VARYING.
+pub fn draw_gouraud_textured_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    texture: &Texture,
+    sampler: &Sampler,
+) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        let light_color = fragment.varyings.get_color(LIGHT_VARYING);
+        let texture_color =
+            sample_texture(sampler, texture, &triangle.material, fragment);
+        (Color::from_argb(texture_color) * light_color).to_argb()
+    });
+}
+
+/// Calculates the Blinn-Phong lit color of a single fragment. The view space
+/// normal and position are expected at NORMAL_VARYING and POSITION_VARYING,
+/// and the world space position at WORLD_POSITION_VARYING.
+fn shade_phong(
+    fragment: &Fragment,
+    surface_color: u32,
+    material: &Material,
+    lights: &[Light],
+    shadow_maps: &[ShadowMap],
+) -> u32 {
+    // Interpolated normals are no longer unit length
+    let normal = Vector3::calc_normalized_vector(
+        &fragment.varyings.get_vector3(NORMAL_VARYING),
+    );
+    let position = fragment.varyings.get_vector3(POSITION_VARYING);
+    let world_position = fragment.varyings.get_vector3(WORLD_POSITION_VARYING);
+
+    let (diffuse, specular) = calc_lighting(
+        lights,
+        shadow_maps,
+        &normal,
+        &position,
+        &world_position,
+        material.shininess,
+    );
+
+    apply_lighting(
+        &Color::from_argb(surface_color),
+        &diffuse,
+        &material.specular_color,
+        &specular,
+    )
+    .to_argb()
+}
+
+/// Draws a triangle with a single color that is lit per pixel
+pub fn draw_phong_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    color: u32,
+    lights: &[Light],
+    shadow_maps: &[ShadowMap],
+) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        shade_phong(fragment, color, &triangle.material, lights, shadow_maps)
+    });
+}
+
+/// Draws a textured triangle that is lit per pixel
+pub fn draw_phong_textured_triangle(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    texture: &Texture,
+    sampler: &Sampler,
+    lights: &[Light],
+    shadow_maps: &[ShadowMap],
+) {
+    draw_shaded_triangle(color_buffer, triangle, |fragment| {
+        shade_phong(
+            fragment,
+            sample_texture(sampler, texture, &triangle.material, fragment),
+            &triangle.material,
+            lights,
+            shadow_maps,
+        )
+    });
+}
+
+/// Draws a triangle whose color is computed per pixel by 'shader', which
+/// receives the perspective-correct interpolated values of the pixel. The
+/// color's alpha is used according to the triangle material's alpha mode.
+pub fn draw_shaded_triangle<F>(
+    color_buffer: &mut ColorBuffer,
+    triangle: &Triangle,
+    shader: F,
+) where
+    F: Fn(&Fragment) -> u32,
+{
+    rasterize_into(color_buffer, triangle, |color_buffer, fragment| {
+        let color = shader(fragment);
+        write_fragment(
+            color_buffer,
+            fragment,
+            color,
+            &triangle.material.alpha_mode,
+        );
+    });
+}
 
+/// Walks every pixel covered by a triangle in screen space and calls
+/// 'fragment_fn' with the interpolated values for that pixel
+pub fn rasterize_triangle<F>(triangle: &Triangle, mut fragment_fn: F)
+where
+    F: FnMut(&Fragment),
+{
     // Find triangle vertex order
     let (vertex0, vertex1, vertex2) = get_sorted_triangle_vertices(triangle);
 
@@ -286,6 +1097,7 @@ pub fn draw_filled_triangle(
             w: vertex0.0.w,
         },
         vertex0.1,
+        vertex0.2,
     );
     let vertex1 = (
         Vector4 {
@@ -295,6 +1107,7 @@ pub fn draw_filled_triangle(
             w: vertex1.0.w,
         },
         vertex1.1,
+        vertex1.2,
     );
     let vertex2 = (
         Vector4 {
@@ -304,8 +1117,13 @@ pub fn draw_filled_triangle(
             w: vertex2.0.w,
         },
         vertex2.1,
+        vertex2.2,
     );
 
+    // The derivatives are constant across the triangle
+    let barycentric_derivatives =
+        calc_barycentric_derivatives(&vertex0, &vertex1, &vertex2);
+
     // Fill flat bottom triangle (y0 to y1)
     if (y_1 - y_0) != 0 {
         // Find inverse slopes (delta-x over delta-y)
@@ -343,15 +1161,16 @@ pub fn draw_filled_triangle(
             };
 
             for x in x_start..=x_end {
-                draw_pixel_zbuffer(
-                    color_buffer,
+                if let Some(fragment) = interpolate_fragment(
                     x,
                     current_y,
                     &vertex0,
                     &vertex1,
                     &vertex2,
-                    color,
-                );
+                    &barycentric_derivatives,
+                ) {
+                    fragment_fn(&fragment);
+                }
             }
         }
     }
@@ -393,106 +1212,49 @@ pub fn draw_filled_triangle(
             };
 
             for x in x_start..=x_end {
-                draw_pixel_zbuffer(
-                    color_buffer,
+                if let Some(fragment) = interpolate_fragment(
                     x,
                     current_y,
                     &vertex0,
                     &vertex1,
                     &vertex2,
-                    color,
-                );
+                    &barycentric_derivatives,
+                ) {
+                    fragment_fn(&fragment);
+                }
             }
         }
     }
 }
 
-fn draw_pixel_zbuffer(
-    color_buffer: &mut ColorBuffer,
+/// Calculates the barycentric coordinates of a pixel relative to a
+/// triangle. Returns None if the pixel is outside of the triangle.
+fn calc_barycentric(
     x: i32,
     y: i32,
-    vertex0: &(Vector4, TextureUv),
-    vertex1: &(Vector4, TextureUv),
-    vertex2: &(Vector4, TextureUv),
-    color: u32,
-) {
-    // Calculate Barycentric coordinates
-    //
-    //         (B)
-    //         /|\
-    //        / | \
-    //       /  |  \
-    //      /  (P)  \
-    //     /  /   \  \
-    //    / /       \ \
-    //   //           \\
-    //  (A)------------(C)
-    //
-    let (alpha, beta, gamma) = {
-        let a = Vector2::from_vector4(&vertex0.0);
-        let b = Vector2::from_vector4(&vertex1.0);
-        let c = Vector2::from_vector4(&vertex2.0);
-        let p = Vector2 {
-            x: x as f32,
-            y: y as f32,
-        };
-
-        let ab = &b - &a;
-        let ac = &c - &a;
-        let ap = &p - &a;
-        let pc = &c - &p;
-        let pb = &b - &p;
-
-        let abc_parallelagram_area = ac.x * ab.y - ac.y * ab.x; // || AC x AB ||
-
-        // Area of PBC divided by the entire area
-        let alpha = (pc.x * pb.y - pc.y * pb.x) / abc_parallelagram_area;
-
-        // Area of APC divided by the entire area
-        let beta = (ac.x * ap.y - ac.y * ap.x) / abc_parallelagram_area;
-
-        // Gamma is the remaining part of the ratio
-        let gamma = 1.0 - alpha - beta;
-
-        (alpha, beta, gamma)
-    };
+    vertex0: &TriangleVertex,
+    vertex1: &TriangleVertex,
+    vertex2: &TriangleVertex,
+) -> Option<(f32, f32, f32)> {
+    let (alpha, beta, gamma) =
+        calc_barycentric_weights(x as f32, y as f32, vertex0, vertex1, vertex2);
 
     if alpha < 0.0 || beta < 0.0 || gamma < 0.0 {
-        return;
+        None
+    } else {
+        Some((alpha, beta, gamma))
     }
-
-    // Interpolate UV values
-    let interpolated_reciprocal_w = {
-        let reciprocal_w_0 = 1.0 / vertex0.0.w;
-        let reciprocal_w_1 = 1.0 / vertex1.0.w;
-        let reciprocal_w_2 = 1.0 / vertex2.0.w;
-
-        let interpolated_reciprocal_w = reciprocal_w_0 * alpha
-            + reciprocal_w_1 * beta
-            + reciprocal_w_2 * gamma;
-
-        interpolated_reciprocal_w
-    };
-
-    // Draw pixel
-    color_buffer.set_pixel_zcell(
-        x as usize,
-        y as usize,
-        interpolated_reciprocal_w,
-        color,
-    );
 }
 
-fn draw_texel(
-    color_buffer: &mut ColorBuffer,
-    x: i32,
-    y: i32,
-    vertex0: &(Vector4, TextureUv),
-    vertex1: &(Vector4, TextureUv),
-    vertex2: &(Vector4, TextureUv),
-    texture: &Texture,
-    light_intensity: f32,
-) {
+/// Calculates the barycentric coordinates of a point relative to a triangle.
+/// Points outside of the triangle have at least one negative coordinate.
+fn calc_barycentric_weights(
+    x: f32,
+    y: f32,
+    vertex0: &TriangleVertex,
+    vertex1: &TriangleVertex,
+    vertex2: &TriangleVertex,
+) -> (f32, f32, f32) {
     // Calculate Barycentric coordinates
     //
     //         (B)
@@ -509,10 +1271,7 @@ fn draw_texel(
         let a = Vector2::from_vector4(&vertex0.0);
         let b = Vector2::from_vector4(&vertex1.0);
         let c = Vector2::from_vector4(&vertex2.0);
-        let p = Vector2 {
-            x: x as f32,
-            y: y as f32,
-        };
+        let p = Vector2 { x, y };
 
         let ab = &b - &a;
         let ac = &c - &a;
@@ -534,200 +1293,248 @@ fn draw_texel(
         (alpha, beta, gamma)
     };
 
-    if alpha < 0.0 || beta < 0.0 || gamma < 0.0 {
-        return;
-    }
-
-    // Interpolate UV values
-    let (interpolated_u, interpolated_v, interpolated_reciprocal_w) = {
-        let uv0 = vertex0.1.clone();
-        let uv1 = vertex1.1.clone();
-        let uv2 = vertex2.1.clone();
-
-        let reciprocal_w_0 = 1.0 / vertex0.0.w;
-        let reciprocal_w_1 = 1.0 / vertex1.0.w;
-        let reciprocal_w_2 = 1.0 / vertex2.0.w;
-
-        // interpolate over the reciprocal of w (our Z-value prior to projection)
-        let interpolated_u = uv0.u * reciprocal_w_0 * alpha
-            + uv1.u * reciprocal_w_1 * beta
-            + uv2.u * reciprocal_w_2 * gamma;
-        let interpolated_v = uv0.v * reciprocal_w_0 * alpha
-            + uv1.v * reciprocal_w_1 * beta
-            + uv2.v * reciprocal_w_2 * gamma;
+    (alpha, beta, gamma)
+}
 
-        let interpolated_reciprocal_w = reciprocal_w_0 * alpha
-            + reciprocal_w_1 * beta
-            + reciprocal_w_2 * gamma;
+/// Calculates how much the barycentric coordinates of a triangle change per
+/// pixel along the screen's x and y axes
+fn calc_barycentric_derivatives(
+    vertex0: &TriangleVertex,
+    vertex1: &TriangleVertex,
+    vertex2: &TriangleVertex,
+) -> ([f32; 3], [f32; 3]) {
+    let a = Vector2::from_vector4(&vertex0.0);
+    let b = Vector2::from_vector4(&vertex1.0);
+    let c = Vector2::from_vector4(&vertex2.0);
+
+    let ab = &b - &a;
+    let ac = &c - &a;
+
+    let abc_parallelagram_area = ac.x * ab.y - ac.y * ab.x; // || AC x AB ||
+
+    // Derivatives of the area ratios used in calc_barycentric
+    let alpha_dx = (c.y - b.y) / abc_parallelagram_area;
+    let alpha_dy = (b.x - c.x) / abc_parallelagram_area;
+    let beta_dx = -ac.y / abc_parallelagram_area;
+    let beta_dy = ac.x / abc_parallelagram_area;
+
+    (
+        [alpha_dx, beta_dx, -alpha_dx - beta_dx],
+        [alpha_dy, beta_dy, -alpha_dy - beta_dy],
+    )
+}
 
-        // Undo reciprocal
-        let interpolated_u = interpolated_u / interpolated_reciprocal_w;
-        let interpolated_v = interpolated_v / interpolated_reciprocal_w;
+/// Finds the perspective-correct UV and varying values for a pixel. Returns
+/// None if the pixel is outside of the triangle.
+fn interpolate_fragment(
+    x: i32,
+    y: i32,
+    vertex0: &TriangleVertex,
+    vertex1: &TriangleVertex,
+    vertex2: &TriangleVertex,
+    barycentric_derivatives: &([f32; 3], [f32; 3]),
+) -> Option<Fragment> {
+    let barycentric = calc_barycentric(x, y, vertex0, vertex1, vertex2)?;
+
+    Some(build_fragment(
+        x,
+        y,
+        barycentric,
+        vertex0,
+        vertex1,
+        vertex2,
+        barycentric_derivatives,
+    ))
+}
 
-        (interpolated_u, interpolated_v, interpolated_reciprocal_w)
+/// Finds the perspective-correct UV and varying values at a point given by
+/// its barycentric coordinates. 'barycentric_derivatives' come from
+/// calc_barycentric_derivatives. The fragment covers sample 0 only.
+fn build_fragment(
+    x: i32,
+    y: i32,
+    (alpha, beta, gamma): (f32, f32, f32),
+    vertex0: &TriangleVertex,
+    vertex1: &TriangleVertex,
+    vertex2: &TriangleVertex,
+    (barycentric_dx, barycentric_dy): &([f32; 3], [f32; 3]),
+) -> Fragment {
+    let reciprocal_w_0 = 1.0 / vertex0.0.w;
+    let reciprocal_w_1 = 1.0 / vertex1.0.w;
+    let reciprocal_w_2 = 1.0 / vertex2.0.w;
+
+    let interpolated_reciprocal_w =
+        reciprocal_w_0 * alpha + reciprocal_w_1 * beta + reciprocal_w_2 * gamma;
+
+    // Interpolate UV values over the reciprocal of w (our Z-value prior to
+    // projection)
+    let uv0 = &vertex0.1;
+    let uv1 = &vertex1.1;
+    let uv2 = &vertex2.1;
+    let u_over_w = [
+        uv0.u * reciprocal_w_0,
+        uv1.u * reciprocal_w_1,
+        uv2.u * reciprocal_w_2,
+    ];
+    let v_over_w = [
+        uv0.v * reciprocal_w_0,
+        uv1.v * reciprocal_w_1,
+        uv2.v * reciprocal_w_2,
+    ];
+    let reciprocal_ws = [reciprocal_w_0, reciprocal_w_1, reciprocal_w_2];
+    let interpolate = |values: &[f32; 3], weights: &[f32; 3]| {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
     };
 
-    // Map UV value to texture coordinates
-    let texture_x =
-        ((texture.width - 1) as f32 * interpolated_u).abs() as usize;
-    let texture_y =
-        ((texture.height - 1) as f32 * interpolated_v).abs() as usize;
+    // Undo reciprocal
+    let barycentric = [alpha, beta, gamma];
+    let uv = TextureUv {
+        u: interpolate(&u_over_w, &barycentric) / interpolated_reciprocal_w,
+        v: interpolate(&v_over_w, &barycentric) / interpolated_reciprocal_w,
+    };
 
-    // Draw pixel
-    let pixel_color = {
-        let pixel_color = texture.get_pixel(texture_x, texture_y);
-        apply_intensity(pixel_color, light_intensity)
+    // The barycentric coordinates change linearly across the screen, so the
+    // derivatives of the perspective-correct UV follow from the quotient
+    // rule: d(n / d) = (dn - (n / d) * dd) / d
+    let calc_uv_derivative = |barycentric_delta: &[f32; 3]| {
+        let reciprocal_w_delta = interpolate(&reciprocal_ws, barycentric_delta);
+        TextureUv {
+            u: (interpolate(&u_over_w, barycentric_delta)
+                - uv.u * reciprocal_w_delta)
+                / interpolated_reciprocal_w,
+            v: (interpolate(&v_over_w, barycentric_delta)
+                - uv.v * reciprocal_w_delta)
+                / interpolated_reciprocal_w,
+        }
     };
-    color_buffer.set_pixel_zcell(
-        x as usize,
-        y as usize,
+    let uv_dx = calc_uv_derivative(barycentric_dx);
+    let uv_dy = calc_uv_derivative(barycentric_dy);
+
+    let varyings = Varyings::interpolate_perspective(
+        [&vertex0.2, &vertex1.2, &vertex2.2],
+        [reciprocal_w_0, reciprocal_w_1, reciprocal_w_2],
+        [alpha, beta, gamma],
         interpolated_reciprocal_w,
-        pixel_color,
     );
+
+    let mut sample_reciprocal_ws = [0.0; MAX_SAMPLES];
+    sample_reciprocal_ws[0] = interpolated_reciprocal_w;
+
+    Fragment {
+        x,
+        y,
+        reciprocal_w: interpolated_reciprocal_w,
+        uv,
+        uv_dx,
+        uv_dy,
+        varyings,
+        coverage: 1,
+        sample_reciprocal_ws,
+    }
 }
 
-pub fn draw_textured_triangle(
-    color_buffer: &mut ColorBuffer,
+/// Walks every pixel that a triangle covers at least one sample of and calls
+/// 'fragment_fn' once for that pixel. 'sample_offsets' are the positions of
+/// the samples relative to each pixel. The fragment's coverage and sample
+/// depths are set for each sample.
+pub fn rasterize_triangle_multisampled<F>(
     triangle: &Triangle,
-    texture: &Texture,
-) {
-    // Find triangle vertex order
-    let (vertex0, vertex1, vertex2) = get_sorted_triangle_vertices(triangle);
-
-    let x_0 = vertex0.0.x as i32;
-    let y_0 = vertex0.0.y as i32;
-    let x_1 = vertex1.0.x as i32;
-    let y_1 = vertex1.0.y as i32;
-    let x_2 = vertex2.0.x as i32;
-    let y_2 = vertex2.0.y as i32;
-
-    let vertex0 = (
-        Vector4 {
-            x: x_0 as f32,
-            y: y_0 as f32,
-            z: vertex0.0.z,
-            w: vertex0.0.w,
-        },
-        vertex0.1,
-    );
-    let vertex1 = (
-        Vector4 {
-            x: x_1 as f32,
-            y: y_1 as f32,
-            z: vertex1.0.z,
-            w: vertex1.0.w,
-        },
-        vertex1.1,
-    );
-    let vertex2 = (
-        Vector4 {
-            x: x_2 as f32,
-            y: y_2 as f32,
-            z: vertex2.0.z,
-            w: vertex2.0.w,
-        },
-        vertex2.1,
-    );
-
-    // Fill flat bottom triangle (y0 to y1)
-    if (y_1 - y_0) != 0 {
-        // Find inverse slopes (delta-x over delta-y)
-        let (inv_slope_1, inv_slope_2) = {
-            let inv_slope_1 = {
-                let denom = (y_1 - y_0).abs();
-                if denom == 0 {
-                    0.0
-                } else {
-                    (x_1 - x_0) as f32 / (denom as f32)
-                }
-            };
-            let inv_slope_2 = {
-                let denom = (y_2 - y_0).abs();
-                if denom == 0 {
-                    0.0
-                } else {
-                    (x_2 - x_0) as f32 / (denom as f32)
-                }
-            };
-
-            (inv_slope_1, inv_slope_2)
-        };
-
-        for current_y in y_0..=y_1 {
-            let x_start =
-                (x_1 as f32 + ((current_y - y_1) as f32 * inv_slope_1)) as i32;
-            let x_end =
-                (x_0 as f32 + ((current_y - y_0) as f32 * inv_slope_2)) as i32;
-
-            let (x_start, x_end) = if x_end < x_start {
-                (x_end, x_start)
-            } else {
-                (x_start, x_end)
-            };
-
-            for x in x_start..=x_end {
-                draw_texel(
-                    color_buffer,
-                    x,
-                    current_y,
-                    &vertex0,
-                    &vertex1,
-                    &vertex2,
-                    texture,
-                    triangle.light_intensity,
+    sample_offsets: &[(f32, f32)],
+    mut fragment_fn: F,
+) where
+    F: FnMut(&Fragment),
+{
+    // Unlike rasterize_triangle, vertices aren't snapped to whole pixels so
+    // that coverage can change within a pixel
+    let vertices: [TriangleVertex; 3] = [0, 1, 2].map(|index| {
+        (
+            triangle.points[index],
+            triangle.texel_coordinates[index].clone(),
+            triangle.varyings[index],
+        )
+    });
+    let reciprocal_ws = vertices.clone().map(|vertex| 1.0 / vertex.0.w);
+    let barycentric_derivatives =
+        calc_barycentric_derivatives(&vertices[0], &vertices[1], &vertices[2]);
+
+    let min_x = vertices
+        .iter()
+        .map(|vertex| vertex.0.x)
+        .fold(f32::MAX, f32::min);
+    let max_x = vertices
+        .iter()
+        .map(|vertex| vertex.0.x)
+        .fold(f32::MIN, f32::max);
+    let min_y = vertices
+        .iter()
+        .map(|vertex| vertex.0.y)
+        .fold(f32::MAX, f32::min);
+    let max_y = vertices
+        .iter()
+        .map(|vertex| vertex.0.y)
+        .fold(f32::MIN, f32::max);
+
+    // Samples may be up to half a pixel away from the pixel's position
+    for y in ((min_y - 0.5).floor() as i32)..=((max_y + 0.5).ceil() as i32) {
+        for x in ((min_x - 0.5).floor() as i32)..=((max_x + 0.5).ceil() as i32)
+        {
+            let mut coverage = 0;
+            let mut sample_reciprocal_ws = [0.0; MAX_SAMPLES];
+            let mut covered_barycentric = None;
+
+            for (sample, (offset_x, offset_y)) in
+                sample_offsets.iter().enumerate().take(MAX_SAMPLES)
+            {
+                let (alpha, beta, gamma) = calc_barycentric_weights(
+                    x as f32 + offset_x,
+                    y as f32 + offset_y,
+                    &vertices[0],
+                    &vertices[1],
+                    &vertices[2],
                 );
-            }
-        }
-    }
-
-    // Fill flat top triangle (y1 to y2)
-    if (y_2 - y_1) != 0 {
-        // Find inverse slopes (delta-x over delta-y)
-        let (inv_slope_0, inv_slope_1) = {
-            let inv_slope_0 = {
-                let denom = (y_2 - y_0).abs();
-                if denom == 0 {
-                    0.0
-                } else {
-                    (x_2 - x_0) as f32 / denom as f32
+                // Written so that NaN (from degenerate triangles) is outside
+                if !(alpha >= 0.0 && beta >= 0.0 && gamma >= 0.0) {
+                    continue;
                 }
-            };
-            let inv_slope_1 = {
-                let denom = (y_2 - y_1).abs();
-                if denom == 0 {
-                    0.0
-                } else {
-                    (x_2 - x_1) as f32 / denom as f32
-                }
-            };
 
-            (inv_slope_0, inv_slope_1)
-        };
-
-        for current_y in y_1..=y_2 {
-            let x_start =
-                ((current_y - y_0) as f32 * inv_slope_0 + x_0 as f32) as i32;
-            let x_end =
-                ((current_y - y_1) as f32 * inv_slope_1 + x_1 as f32) as i32;
+                coverage |= 1 << sample;
+                sample_reciprocal_ws[sample] = alpha * reciprocal_ws[0]
+                    + beta * reciprocal_ws[1]
+                    + gamma * reciprocal_ws[2];
+                covered_barycentric.get_or_insert((alpha, beta, gamma));
+            }
 
-            let (x_start, x_end) = if x_end < x_start {
-                (x_end, x_start)
-            } else {
-                (x_start, x_end)
+            // Shade once per pixel at the pixel's position. If that is outside
+            // of the triangle, shade at a covered sample instead so that
+            // attributes aren't extrapolated.
+            let barycentric = match (
+                calc_barycentric(
+                    x,
+                    y,
+                    &vertices[0],
+                    &vertices[1],
+                    &vertices[2],
+                ),
+                covered_barycentric,
+            ) {
+                (_, None) => continue,
+                (Some(barycentric), _) => barycentric,
+                (None, Some(barycentric)) => barycentric,
             };
 
-            for x in x_start..=x_end {
-                draw_texel(
-                    color_buffer,
-                    x,
-                    current_y,
-                    &vertex0,
-                    &vertex1,
-                    &vertex2,
-                    texture,
-                    triangle.light_intensity,
-                );
-            }
+            let mut fragment = build_fragment(
+                x,
+                y,
+                barycentric,
+                &vertices[0],
+                &vertices[1],
+                &vertices[2],
+                &barycentric_derivatives,
+            );
+            fragment.coverage = coverage;
+            fragment.sample_reciprocal_ws = sample_reciprocal_ws;
+
+            fragment_fn(&fragment);
         }
     }
 }
@@ -765,13 +1572,16 @@ pub fn parallelizable_draw_triangle(
 
     for x in xmin..=xmax {
         for y in ymin..=ymax {
-            let p = Vector2 {x: x as f32, y: y as f32};
+            let p = Vector2 {
+                x: x as f32,
+                y: y as f32,
+            };
             let point_in_triangle = {
                 let w0 = edge_cross(&v1, &v2, &p);
                 let w1 = edge_cross(&v2, &v0, &p);
                 let w2 = edge_cross(&v0, &v1, &p);
-                
-                w0 >= 0 && w1 >=0 && w2 >=0
+
+                w0 >= 0 && w1 >= 0 && w2 >= 0
             };
             if point_in_triangle {
                 color_buffer.set_pixel(x as usize, y as usize, triangle.color);
@@ -779,3 +1589,197 @@ pub fn parallelizable_draw_triangle(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPAQUE_BLACK: u32 = 0xFF000000;
+    const HALF_RED: u32 = 0x80FF0000;
+    const HALF_GREEN: u32 = 0x8000FF00;
+    const HALF_BLUE: u32 = 0x800000FF;
+
+    /// Returns true if every channel of two colors differs by at most 'max'
+    fn colors_match(a: u32, b: u32, max: u32) -> bool {
+        (0..4).all(|channel| {
+            let a = (a >> (8 * channel)) & 0xFF;
+            let b = (b >> (8 * channel)) & 0xFF;
+            a.abs_diff(b) <= max
+        })
+    }
+
+    /// Blends fragments of (color, reciprocal z) into a single pixel through
+    /// an A-buffer and returns the resolved color
+    fn resolve_pixel(fragments: &[(u32, f32)], max_fragments: usize) -> u32 {
+        let mut color_buffer = ColorBuffer::new(1, 1);
+        color_buffer.set_a_buffer_enabled(true, max_fragments);
+        color_buffer.clear(OPAQUE_BLACK);
+        for &(color, reciprocal_z) in fragments {
+            color_buffer.blend_pixel_zcell(0, 0, reciprocal_z, color);
+        }
+        color_buffer.resolve_transparency();
+
+        color_buffer.buffer[0]
+    }
+
+    #[test]
+    fn add_fragment_merges_the_farthest_pair_when_full() {
+        let near = (HALF_RED, 0.7);
+        let middle = (HALF_GREEN, 0.5);
+        let far = (HALF_BLUE, 0.3);
+
+        // The new fragment is either the farthest or takes the slot of the
+        // farthest stored fragment
+        for order in [[near, middle, far], [far, near, middle]] {
+            let mut a_buffer = ABuffer::new(1, 1, 2);
+            for (color, reciprocal_z) in order {
+                a_buffer.add_fragment(
+                    0,
+                    TransparentFragment {
+                        color,
+                        reciprocal_z,
+                    },
+                );
+            }
+            assert_eq!(a_buffer.fragment_counts[0], 2);
+
+            let mut stored: Vec<(u32, f32)> = a_buffer
+                .fragments
+                .iter()
+                .map(|fragment| (fragment.color, fragment.reciprocal_z))
+                .collect();
+            stored.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            // The nearest fragment is kept exactly, and the farther two are
+            // merged at the depth of the nearer of them
+            assert!(stored[1] == near);
+            assert!(stored[0] == (composite_over(middle.0, far.0), middle.1));
+        }
+
+        // Merging only loses precision to rounding
+        let exact = resolve_pixel(&[near, middle, far], 3);
+        let merged = resolve_pixel(&[near, middle, far], 2);
+        assert!(
+            colors_match(exact, merged, 1),
+            "{:08X} != {:08X}",
+            exact,
+            merged
+        );
+    }
+
+    #[test]
+    fn resolve_transparency_composites_by_depth_per_pixel() {
+        // Two intersecting triangles drawn in the same order: red is in front
+        // at the left pixel and blue is in front at the right pixel
+        let mut color_buffer = ColorBuffer::new(3, 1);
+        color_buffer.set_a_buffer_enabled(true, 4);
+        color_buffer.clear(OPAQUE_BLACK);
+        color_buffer.blend_pixel_zcell(0, 0, 0.6, HALF_RED);
+        color_buffer.blend_pixel_zcell(1, 0, 0.4, HALF_RED);
+        color_buffer.blend_pixel_zcell(2, 0, 0.4, HALF_RED);
+        color_buffer.blend_pixel_zcell(0, 0, 0.4, HALF_BLUE);
+        color_buffer.blend_pixel_zcell(1, 0, 0.6, HALF_BLUE);
+        color_buffer.blend_pixel_zcell(2, 0, 0.6, HALF_BLUE);
+
+        // An opaque surface drawn afterwards between the two hides red
+        color_buffer.set_pixel_zcell(2, 0, 0.5, 0xFF00FF00);
+
+        color_buffer.resolve_transparency();
+
+        let red_in_front =
+            composite_over(HALF_RED, composite_over(HALF_BLUE, OPAQUE_BLACK));
+        let blue_in_front =
+            composite_over(HALF_BLUE, composite_over(HALF_RED, OPAQUE_BLACK));
+        assert!(red_in_front != blue_in_front);
+        assert_eq!(color_buffer.buffer[0], red_in_front);
+        assert_eq!(color_buffer.buffer[1], blue_in_front);
+        assert_eq!(
+            color_buffer.buffer[2],
+            composite_over(HALF_BLUE, 0xFF00FF00)
+        );
+
+        // The A-buffer is emptied
+        assert!(color_buffer
+            .a_buffer
+            .as_ref()
+            .unwrap()
+            .fragment_counts
+            .iter()
+            .all(|&count| count == 0));
+    }
+
+    /// Makes an opaque white triangle that covers everything right of the
+    /// vertical line at 'edge_x' in the first few rows
+    fn make_half_plane_triangle(edge_x: f32) -> Triangle {
+        let mut triangle = Triangle {
+            light_color: Color::from_argb(0xFFFFFFFF),
+            ..Triangle::default()
+        };
+        triangle.points =
+            [(edge_x, -8.0), (edge_x, 12.0), (edge_x + 20.0, 2.0)].map(
+                |(x, y)| Vector4 {
+                    x,
+                    y,
+                    z: 0.5,
+                    w: 1.0,
+                },
+            );
+
+        triangle
+    }
+
+    /// Checks that each row of a 4x2 buffer is black, half covered, white
+    /// and white
+    fn assert_half_covered_second_column(color_buffer: &ColorBuffer) {
+        let half_white = Color::rgb(0.5, 0.5, 0.5).to_argb();
+        for y in 0..2 {
+            let row = &color_buffer.buffer[(y * 4)..((y + 1) * 4)];
+            assert!(
+                row[0] == OPAQUE_BLACK
+                    && colors_match(row[1], half_white, 1)
+                    && row[2] == 0xFFFFFFFF
+                    && row[3] == 0xFFFFFFFF,
+                "row {}: {:08X?}",
+                y,
+                row
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_samples_averages_a_half_covered_pixel() {
+        // The edge goes through the centers of the second column, and every
+        // sample pattern has half of its samples on each side
+        for sample_count in [2, 4, 8] {
+            let mut color_buffer = ColorBuffer::new(4, 2);
+            color_buffer.set_sample_count(sample_count);
+            color_buffer.clear(OPAQUE_BLACK);
+            draw_filled_triangle(
+                &mut color_buffer,
+                &make_half_plane_triangle(1.0),
+                0xFFFFFFFF,
+            );
+            color_buffer.resolve_samples();
+
+            assert_half_covered_second_column(&color_buffer);
+        }
+    }
+
+    #[test]
+    fn downsample_into_averages_a_half_covered_pixel() {
+        // At twice the resolution, the edge covers the right half of the
+        // second column's blocks
+        let mut supersample_buffer = ColorBuffer::new(8, 4);
+        supersample_buffer.clear(OPAQUE_BLACK);
+        draw_filled_triangle(
+            &mut supersample_buffer,
+            &make_half_plane_triangle(3.0),
+            0xFFFFFFFF,
+        );
+
+        let mut color_buffer = ColorBuffer::new(4, 2);
+        supersample_buffer.downsample_into(&mut color_buffer);
+
+        assert_half_covered_second_column(&color_buffer);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    camera::Camera,
+    clipping::{clip_triangle, FrustumPlanes},
+    light_source::{Light, LightKind},
+    matrix::Matrix4,
+    projection::{
+        make_orthographic_matrix, make_projection_matrix, project_point,
+        project_triangles,
+    },
+    render::rasterize_triangle,
+    triangle::Triangle,
+    vector::{Vector3, Vector4},
+};
+
+pub const SHADOW_MAP_SIZE: usize = 1024;
+
+const SPOT_SHADOW_ZNEAR: f32 = 0.1;
+const SPOT_SHADOW_ZFAR: f32 = 50.0;
+
+/// A depth buffer rendered from the point of view of a light. Surfaces that
+/// are farther from the light than the depth stored in the shadow map are in
+/// shadow.
+pub struct ShadowMap {
+    /// Whether the shadow map was rendered this frame. Inactive shadow maps
+    /// never shadow anything.
+    pub active: bool,
+    /// Offset (in world units) subtracted from a surface's depth before it is
+    /// compared against the shadow map. Prevents surfaces from shadowing
+    /// themselves ("shadow acne").
+    pub depth_bias: f32,
+    /// Number of neighboring shadow map cells in each direction that are
+    /// averaged for percentage-closer filtering. 0 disables filtering.
+    pub pcf_radius: i32,
+    view_matrix: Matrix4,
+    projection_matrix: Matrix4,
+    frustum_planes: FrustumPlanes,
+    width: usize,
+    height: usize,
+    /// Light space depth of the closest surface for each cell. Cells that
+    /// nothing was drawn into are infinitely far away.
+    depth_buffer: Vec<f32>,
+}
+
+impl ShadowMap {
+    pub fn new(size: usize) -> Self {
+        Self {
+            active: false,
+            depth_bias: 0.05,
+            pcf_radius: 1,
+            view_matrix: Matrix4::identity(),
+            projection_matrix: Matrix4::identity(),
+            frustum_planes: FrustumPlanes::new(0.1, 1.0, 1.0, 1.0),
+            width: size,
+            height: size,
+            depth_buffer: vec![f32::INFINITY; size * size],
+        }
+    }
+
+    /// Sets up the shadow map for a world space light and clears it. The
+    /// shadow map covers a sphere of 'radius' around 'focus'. Returns false if
+    /// the light can't cast shadows (ambient and point lights).
+    pub fn setup(
+        &mut self,
+        light: &Light,
+        focus: &Vector3,
+        radius: f32,
+    ) -> bool {
+        let (camera, projection_matrix, frustum_planes) = match light.kind {
+            LightKind::Directional { direction } => {
+                // Place the light outside of the covered sphere, looking at
+                // its center
+                let position = focus - &((2.0 * radius) * &direction);
+                let camera = Camera {
+                    position,
+                    target: *focus,
+                    up: calc_up_vector(&direction),
+                };
+
+                let znear = 0.0;
+                let zfar = 4.0 * radius;
+
+                (
+                    camera,
+                    make_orthographic_matrix(radius, radius, znear, zfar),
+                    FrustumPlanes::new_orthographic(
+                        radius, radius, znear, zfar,
+                    ),
+                )
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                outer_angle,
+                ..
+            } => {
+                let camera = Camera {
+                    position,
+                    target: &position + &direction,
+                    up: calc_up_vector(&direction),
+                };
+
+                let fov = 2.0 * outer_angle;
+
+                (
+                    camera,
+                    make_projection_matrix(
+                        fov,
+                        1.0,
+                        SPOT_SHADOW_ZNEAR,
+                        SPOT_SHADOW_ZFAR,
+                    ),
+                    FrustumPlanes::new(
+                        SPOT_SHADOW_ZNEAR,
+                        SPOT_SHADOW_ZFAR,
+                        fov,
+                        fov,
+                    ),
+                )
+            }
+            LightKind::Ambient | LightKind::Point { .. } => {
+                self.active = false;
+                return false;
+            }
+        };
+
+        self.view_matrix = camera.view_matrix();
+        self.projection_matrix = projection_matrix;
+        self.frustum_planes = frustum_planes;
+        self.depth_buffer.fill(f32::INFINITY);
+        self.active = true;
+
+        true
+    }
+
+    /// Renders the depth of a world space triangle into the shadow map
+    pub fn draw_triangle(&mut self, world_vertices: &[Vector3; 3]) {
+        // The depth from the light is carried as a varying so that it is
+        // interpolated correctly for both perspective and orthographic
+        // projections
+        let mut triangle = Triangle::default();
+        for (index, vertex) in world_vertices.iter().enumerate() {
+            let light_space_vertex = Matrix4::mult_vector(
+                &self.view_matrix,
+                &Vector4::from_vector3(vertex),
+            );
+            triangle.varyings[index].push(light_space_vertex.z);
+            triangle.points[index] = light_space_vertex;
+        }
+
+        let mut clipped_triangles =
+            clip_triangle(&self.frustum_planes, triangle);
+        let mut projected_triangles = vec![];
+        project_triangles(
+            &self.projection_matrix,
+            self.width as u32,
+            self.height as u32,
+            &mut clipped_triangles,
+            &mut projected_triangles,
+        );
+
+        for triangle in &projected_triangles {
+            rasterize_triangle(triangle, |fragment| {
+                if fragment.x < 0
+                    || fragment.y < 0
+                    || fragment.x as usize >= self.width
+                    || fragment.y as usize >= self.height
+                {
+                    return;
+                }
+
+                let depth = fragment.varyings.get(0);
+                let index =
+                    fragment.y as usize * self.width + fragment.x as usize;
+                if depth > 0.0 && depth < self.depth_buffer[index] {
+                    self.depth_buffer[index] = depth;
+                }
+            });
+        }
+    }
+
+    /// Calculates the fraction of the light in [0.0, 1.0] that reaches a
+    /// world space position
+    pub fn calc_light_factor(&self, world_position: &Vector3) -> f32 {
+        if !self.active {
+            return 1.0;
+        }
+
+        let light_space_position = Matrix4::mult_vector(
+            &self.view_matrix,
+            &Vector4::from_vector3(world_position),
+        );
+        if light_space_position.z <= 0.0 {
+            return 1.0;
+        }
+
+        let projected_position = match project_point(
+            &self.projection_matrix,
+            self.width as u32,
+            self.height as u32,
+            &light_space_position,
+        ) {
+            Some(projected_position) => projected_position,
+            None => return 1.0,
+        };
+
+        let depth = light_space_position.z - self.depth_bias;
+        let center_x = projected_position.x as i32;
+        let center_y = projected_position.y as i32;
+
+        // Percentage-closer filtering: average the depth test results of the
+        // neighboring cells rather than filtering the depths themselves
+        let mut lit_count = 0;
+        let mut sample_count = 0;
+        for y in (center_y - self.pcf_radius)..=(center_y + self.pcf_radius) {
+            for x in (center_x - self.pcf_radius)..=(center_x + self.pcf_radius)
+            {
+                sample_count += 1;
+
+                // Anything outside of the shadow map is lit
+                if x < 0
+                    || y < 0
+                    || x >= self.width as i32
+                    || y >= self.height as i32
+                {
+                    lit_count += 1;
+                    continue;
+                }
+
+                let occluder_depth =
+                    self.depth_buffer[y as usize * self.width + x as usize];
+                if depth <= occluder_depth {
+                    lit_count += 1;
+                }
+            }
+        }
+
+        lit_count as f32 / sample_count as f32
+    }
+}
+
+/// Chooses an up vector for a light's view that isn't parallel to its
+/// direction
+fn calc_up_vector(direction: &Vector3) -> Vector3 {
+    if direction.y.abs() > 0.99 {
+        Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        }
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    }
+}
@@ -1,4 +1,14 @@
 use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::{
+    bmp::decode_bmp,
+    color::Color,
+    material::{AlphaMode, DEFAULT_ALPHA_CUTOFF},
+    ppm::decode_ppm,
+    tga::decode_tga,
+};
 
 #[derive(Clone, Default)]
 pub struct TextureUv {
@@ -6,48 +16,544 @@ pub struct TextureUv {
     pub v: f32,
 }
 
-pub struct Texture {
+/// Describes how texel coordinates outside of a texture are mapped back into
+/// it
+#[derive(Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    /// Tiles the texture
+    Repeat,
+    /// Uses the texel at the nearest edge
+    ClampToEdge,
+    /// Tiles the texture, flipping every other tile
+    MirroredRepeat,
+}
+
+impl WrapMode {
+    /// Maps a texel coordinate along an axis of 'size' texels into [0, size)
+    pub fn wrap(&self, coordinate: i32, size: usize) -> usize {
+        let size = size as i32;
+        match self {
+            WrapMode::Repeat => coordinate.rem_euclid(size) as usize,
+            WrapMode::ClampToEdge => coordinate.clamp(0, size - 1) as usize,
+            WrapMode::MirroredRepeat => {
+                // Every pair of tiles is the texture followed by its mirror
+                let period = 2 * size;
+                let coordinate = coordinate.rem_euclid(period);
+                if coordinate < size {
+                    coordinate as usize
+                } else {
+                    (period - 1 - coordinate) as usize
+                }
+            }
+        }
+    }
+}
+
+/// A single level of a texture's mip chain
+pub struct MipLevel {
     pub width: usize,
     pub height: usize,
     pub data: Vec<u32>,
 }
 
-impl Texture {
+impl MipLevel {
     pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
         self.data[(self.width * y) + x]
     }
+
+    /// Builds the next level of the mip chain, which is half the size of this
+    /// level (rounded down, but at least 1). Each texel is the box filtered
+    /// average of the area it covers in this level. For odd sizes that area
+    /// includes fractions of texels, which are weighted by how much of them
+    /// is covered.
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let x_scale = self.width as f32 / width as f32;
+        let y_scale = self.height as f32 / height as f32;
+
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let y_start = y as f32 * y_scale;
+            let y_end = y_start + y_scale;
+
+            for x in 0..width {
+                let x_start = x as f32 * x_scale;
+                let x_end = x_start + x_scale;
+
+                let mut sum = Color::default();
+                let mut total_weight = 0.0;
+                for source_y in (y_start.floor() as usize)
+                    ..(y_end.ceil() as usize).min(self.height)
+                {
+                    let y_coverage = y_end.min(source_y as f32 + 1.0)
+                        - y_start.max(source_y as f32);
+
+                    for source_x in (x_start.floor() as usize)
+                        ..(x_end.ceil() as usize).min(self.width)
+                    {
+                        let x_coverage = x_end.min(source_x as f32 + 1.0)
+                            - x_start.max(source_x as f32);
+
+                        let weight = x_coverage * y_coverage;
+                        let color = Color::from_argb(
+                            self.get_pixel(source_x, source_y),
+                        );
+                        sum = &sum + &(weight * &color);
+                        total_weight += weight;
+                    }
+                }
+
+                data.push((sum * (1.0 / total_weight)).to_argb());
+            }
+        }
+
+        MipLevel {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+pub struct Texture {
+    /// Width of the full resolution texture
+    pub width: usize,
+    /// Height of the full resolution texture
+    pub height: usize,
+    /// The mip chain, starting with the full resolution texture and ending
+    /// with a 1x1 level
+    pub levels: Vec<MipLevel>,
+    /// How the texture's alpha channel should be drawn: opaque if every texel
+    /// is opaque, alpha tested if every texel is either opaque or fully
+    /// transparent (cutouts such as foliage) and blended otherwise
+    pub alpha_mode: AlphaMode,
+}
+
+impl Texture {
+    /// Creates a texture from ARGB data and builds its mip chain. Panics if
+    /// the texture is empty or the data doesn't match its size, since it
+    /// couldn't be sampled.
+    pub fn new(width: usize, height: usize, data: Vec<u32>) -> Self {
+        assert!(
+            width > 0 && height > 0 && data.len() == width * height,
+            "Bad texture data for {}x{}",
+            width,
+            height
+        );
+
+        let alpha_mode = calc_alpha_mode(&data);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            data,
+        }];
+        loop {
+            let level = levels.last().unwrap();
+            if level.width == 1 && level.height == 1 {
+                break;
+            }
+            let next_level = level.downsample();
+            levels.push(next_level);
+        }
+
+        Self {
+            width,
+            height,
+            levels,
+            alpha_mode,
+        }
+    }
+}
+
+/// Finds how ARGB texels need to be drawn for their alpha to be respected
+fn calc_alpha_mode(data: &[u32]) -> AlphaMode {
+    let mut alpha_mode = AlphaMode::Opaque;
+    for texel in data {
+        match texel >> 24 {
+            0xFF => {}
+            0x00 => {
+                alpha_mode = AlphaMode::Test {
+                    cutoff: DEFAULT_ALPHA_CUTOFF,
+                }
+            }
+            _ => return AlphaMode::Blend,
+        }
+    }
+
+    alpha_mode
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    /// Uses the single texel closest to the UV coordinate
+    Nearest,
+    /// Blends the four texels surrounding the UV coordinate by their distance
+    /// to it
+    Bilinear,
+}
+
+impl FilterMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterMode::Nearest => "nearest",
+            FilterMode::Bilinear => "bilinear",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MipmapMode {
+    /// Always samples the full resolution texture
+    Disabled,
+    /// Samples the single mip level closest to the pixel's footprint
+    Nearest,
+    /// Blends between the two mip levels closest to the pixel's footprint.
+    /// Combined with bilinear filtering this is trilinear filtering.
+    Linear,
+}
+
+impl MipmapMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MipmapMode::Disabled => "disabled",
+            MipmapMode::Nearest => "nearest",
+            MipmapMode::Linear => "linear",
+        }
+    }
+}
+
+/// Describes how a texture is read at a UV coordinate
+#[derive(Clone, Copy)]
+pub struct Sampler {
+    pub filter_mode: FilterMode,
+    pub mipmap_mode: MipmapMode,
+    /// The largest ratio between the long and short sides of a pixel's
+    /// footprint that is sampled anisotropically. 1 disables anisotropic
+    /// filtering.
+    pub max_anisotropy: u32,
+    /// How texel coordinates outside of the texture are mapped back into it
+    /// along U and V. When drawing triangles these come from the triangle's
+    /// material.
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        // Sample the full resolution texture without any filtering, so
+        // filtering and mipmapping are opt-in
+        Self {
+            filter_mode: FilterMode::Nearest,
+            mipmap_mode: MipmapMode::Disabled,
+            max_anisotropy: 1,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+        }
+    }
+}
+
+impl Sampler {
+    /// Gets the color of a texture at a UV coordinate. UVs outside of
+    /// [0.0, 1.0] are handled by the sampler's wrap modes. 'uv_dx' and
+    /// 'uv_dy' are the changes in UV from one pixel to the next along the
+    /// screen's x and y axes, and are used to choose a mip level.
+    pub fn sample(
+        &self,
+        texture: &Texture,
+        uv: &TextureUv,
+        uv_dx: &TextureUv,
+        uv_dy: &TextureUv,
+    ) -> u32 {
+        // Measure the pixel's footprint in full resolution texels
+        let dx_length = (uv_dx.u * texture.width as f32)
+            .hypot(uv_dx.v * texture.height as f32);
+        let dy_length = (uv_dy.u * texture.width as f32)
+            .hypot(uv_dy.v * texture.height as f32);
+
+        if self.max_anisotropy <= 1 {
+            return self
+                .sample_lod(texture, uv, dx_length.max(dy_length))
+                .to_argb();
+        }
+
+        // Anisotropic filtering: take several samples along the long axis of
+        // the footprint, each from the mip level that fits the short axis
+        let (major_length, minor_length, major_axis) = if dx_length > dy_length
+        {
+            (dx_length, dy_length, uv_dx)
+        } else {
+            (dy_length, dx_length, uv_dy)
+        };
+        let ratio = major_length / minor_length.max(f32::MIN_POSITIVE);
+        let ratio = if ratio.is_nan() {
+            1.0
+        } else {
+            ratio.clamp(1.0, self.max_anisotropy as f32)
+        };
+        let sample_count = ratio.ceil() as usize;
+
+        let mut sum = Color::default();
+        for sample_index in 0..sample_count {
+            // Spread the samples evenly across the footprint
+            let offset =
+                (sample_index as f32 + 0.5) / sample_count as f32 - 0.5;
+            let sample_uv = TextureUv {
+                u: uv.u + offset * major_axis.u,
+                v: uv.v + offset * major_axis.v,
+            };
+            sum = &sum
+                + &self.sample_lod(texture, &sample_uv, major_length / ratio);
+        }
+
+        (sum * (1.0 / sample_count as f32)).to_argb()
+    }
+
+    /// Samples the texture with a footprint of 'texel_footprint' full
+    /// resolution texels per pixel
+    fn sample_lod(
+        &self,
+        texture: &Texture,
+        uv: &TextureUv,
+        texel_footprint: f32,
+    ) -> Color {
+        let max_level = texture.levels.len() - 1;
+        let lod = texel_footprint.log2();
+        let lod = if lod.is_nan() {
+            0.0
+        } else {
+            lod.clamp(0.0, max_level as f32)
+        };
+
+        match self.mipmap_mode {
+            MipmapMode::Disabled => self.sample_level(texture, 0, uv),
+            MipmapMode::Nearest => {
+                self.sample_level(texture, lod.round() as usize, uv)
+            }
+            MipmapMode::Linear => {
+                let level = lod.floor() as usize;
+                let next_level = (level + 1).min(max_level);
+                let weight = lod - level as f32;
+
+                &((1.0 - weight) * &self.sample_level(texture, level, uv))
+                    + &(weight * &self.sample_level(texture, next_level, uv))
+            }
+        }
+    }
+
+    fn sample_level(
+        &self,
+        texture: &Texture,
+        level: usize,
+        uv: &TextureUv,
+    ) -> Color {
+        let level = &texture.levels[level];
+        match self.filter_mode {
+            FilterMode::Nearest => self.sample_nearest(level, uv),
+            FilterMode::Bilinear => self.sample_bilinear(level, uv),
+        }
+    }
+
+    fn sample_nearest(&self, level: &MipLevel, uv: &TextureUv) -> Color {
+        // Map UV value to texture coordinates
+        let texture_x = to_texel_coordinate(uv.u * level.width as f32);
+        let texture_y = to_texel_coordinate(uv.v * level.height as f32);
+
+        Color::from_argb(self.get_wrapped_pixel(level, texture_x, texture_y))
+    }
+
+    fn sample_bilinear(&self, level: &MipLevel, uv: &TextureUv) -> Color {
+        // Texel centers are at half-integer coordinates, so shift by half a
+        // texel to find the texel whose center is up and to the left of the UV
+        let x = uv.u * level.width as f32 - 0.5;
+        let y = uv.v * level.height as f32 - 0.5;
+        let x_weight = (x - x.floor()).clamp(0.0, 1.0);
+        let y_weight = (y - y.floor()).clamp(0.0, 1.0);
+        let x0 = to_texel_coordinate(x);
+        let y0 = to_texel_coordinate(y);
+
+        let get_color =
+            |x, y| Color::from_argb(self.get_wrapped_pixel(level, x, y));
+        let top_left = get_color(x0, y0);
+        let top_right = get_color(x0 + 1, y0);
+        let bottom_left = get_color(x0, y0 + 1);
+        let bottom_right = get_color(x0 + 1, y0 + 1);
+
+        let top = &((1.0 - x_weight) * &top_left) + &(x_weight * &top_right);
+        let bottom =
+            &((1.0 - x_weight) * &bottom_left) + &(x_weight * &bottom_right);
+
+        &((1.0 - y_weight) * &top) + &(y_weight * &bottom)
+    }
+
+    /// Gets the pixel of a mip level at signed texel coordinates, applying
+    /// the sampler's wrap modes to coordinates that fall outside of it
+    fn get_wrapped_pixel(&self, level: &MipLevel, x: i32, y: i32) -> u32 {
+        let x = self.wrap_u.wrap(x, level.width);
+        let y = self.wrap_v.wrap(y, level.height);
+
+        level.get_pixel(x, y)
+    }
+}
+
+/// Converts a UV component to a texel coordinate. NaN maps to 0 and values
+/// too large for an i32 saturate; the margin leaves room for neighboring
+/// texels to be addressed without overflowing.
+fn to_texel_coordinate(value: f32) -> i32 {
+    value.floor().clamp(-1.0e9, 1.0e9) as i32
 }
 
-pub fn load_png_texture(path: &String) -> Texture {
+/// Loads a PNG, TGA, BMP, PPM or PGM texture. The format is detected from
+/// the file's magic bytes, falling back to its extension for TGA files, which
+/// have no magic bytes.
+pub fn load_texture(path: &String) -> Result<Texture, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => {
+            return Err(format!("Unable to open {}: {}", path, error))
+        }
+    };
+
+    let mut data = vec![];
+    match file.read_to_end(&mut data) {
+        Ok(_) => {}
+        Err(error) => {
+            return Err(format!("Unable to read {}: {}", path, error))
+        }
+    }
+
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    let result = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        decode_png(&data)
+    } else if data.starts_with(b"BM") {
+        decode_bmp(&data)
+    } else if data.starts_with(b"P2")
+        || data.starts_with(b"P3")
+        || data.starts_with(b"P5")
+        || data.starts_with(b"P6")
+    {
+        decode_ppm(&data)
+    } else if extension.as_deref() == Some("tga") {
+        decode_tga(&data)
+    } else {
+        return Err(format!("Unrecognized texture format: {}", path));
+    };
+
+    match result {
+        Ok(texture) => Ok(texture),
+        Err(error) => Err(format!("Unable to decode {}: {}", path, error)),
+    }
+}
+
+/// Decodes a PNG of any color type and bit depth
+fn decode_png(data: &[u8]) -> Result<Texture, String> {
     // The decoder is a build for reader and can be used to set various decoding options
-    // via `Transformations`. The default output transformation is `Transformations::IDENTITY`.
-    let decoder = png::Decoder::new(File::open(path).unwrap());
-    let mut reader = decoder.read_info().unwrap();
+    // via `Transformations`. EXPAND turns palette images into RGB(A), expands
+    // grayscale images below 8 bits, and turns tRNS chunks into an alpha
+    // channel. STRIP_16 reduces 16 bit channels to 8 bits. Together they
+    // leave only 8 bit grayscale, grayscale+alpha, RGB and RGBA output.
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(
+        png::Transformations::EXPAND | png::Transformations::STRIP_16,
+    );
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(error) => return Err(error.to_string()),
+    };
     // Allocate the output buffer.
     let mut buf = vec![0; reader.output_buffer_size()];
     // Read the next frame. An APNG might contain multiple frames.
-    let info = reader.next_frame(&mut buf).unwrap();
+    let info = match reader.next_frame(&mut buf) {
+        Ok(info) => info,
+        Err(error) => return Err(error.to_string()),
+    };
     let width = info.width as usize;
     let height = info.height as usize;
 
-    let mut u32_buffer =
-        Vec::<u32>::with_capacity(reader.output_buffer_size() / 4);
-    for index in 0..(width * height) {
-        // PNG has data as RGBA but we want ARGB
-        let red = (buf[4 * index] as u32) << 16;
-        let green = (buf[4 * index + 1] as u32) << 8;
-        let blue = (buf[4 * index + 2] as u32);
-        let alpha = (buf[4 * index + 3] as u32) << 24;
-        u32_buffer.push(alpha + red + green + blue);
+    if info.bit_depth != png::BitDepth::Eight {
+        return Err(format!("Unsupported PNG bit depth {:?}", info.bit_depth));
+    }
+    let channel_count = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err("Unexpanded PNG palette".to_string())
+        }
+    };
+
+    let mut u32_buffer = Vec::<u32>::with_capacity(width * height);
+    for y in 0..height {
+        let row = &buf[(y * info.line_size)..];
+        for x in 0..width {
+            let pixel = &row[(x * channel_count)..((x + 1) * channel_count)];
+
+            // PNG has data as (gray or RGB) followed by an optional alpha but
+            // we want ARGB
+            let (red, green, blue, alpha) = match pixel {
+                [gray] => (*gray, *gray, *gray, 0xFF),
+                [gray, alpha] => (*gray, *gray, *gray, *alpha),
+                [red, green, blue] => (*red, *green, *blue, 0xFF),
+                [red, green, blue, alpha, ..] => (*red, *green, *blue, *alpha),
+                _ => unreachable!(),
+            };
+            u32_buffer.push(pack_argb(alpha, red, green, blue));
+        }
+    }
+
+    Ok(Texture::new(width, height, u32_buffer))
+}
+
+/// Packs 8 bit channels into an ARGB color
+pub fn pack_argb(alpha: u8, red: u8, green: u8, blue: u8) -> u32 {
+    ((alpha as u32) << 24)
+        | ((red as u32) << 16)
+        | ((green as u32) << 8)
+        | blue as u32
+}
+
+/// Reads a little endian u16 from image data
+pub fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, String> {
+    match data.get(offset..(offset + 2)) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => Err("Image data is truncated".to_string()),
     }
+}
 
-    Texture {
-        width: width,
-        height: height,
-        data: u32_buffer,
+/// Reads a little endian u32 from image data
+pub fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, String> {
+    match data.get(offset..(offset + 4)) {
+        Some(bytes) => {
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+        None => Err("Image data is truncated".to_string()),
     }
 }
 
+/// Creates a magenta and black checkerboard that stands in for textures that
+/// couldn't be loaded
+pub fn load_checkerboard_texture() -> Texture {
+    const SIZE: usize = 64;
+    const CHECKER_SIZE: usize = 8;
+
+    let mut data = Vec::with_capacity(SIZE * SIZE);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            if (x / CHECKER_SIZE + y / CHECKER_SIZE).is_multiple_of(2) {
+                data.push(0xFFFF00FF);
+            } else {
+                data.push(0xFF000000);
+            }
+        }
+    }
+
+    Texture::new(SIZE, SIZE, data)
+}
+
 pub fn load_test_texture() -> Texture {
     let mut data =
         Vec::with_capacity(REDBRICK_TEXTURE_WIDTH * REDBRICK_TEXTURE_HEIGHT);
@@ -59,11 +565,7 @@ pub fn load_test_texture() -> Texture {
         data.push(alpha + red + green + blue);
     }
 
-    Texture {
-        width: REDBRICK_TEXTURE_WIDTH,
-        height: REDBRICK_TEXTURE_HEIGHT,
-        data,
-    }
+    Texture::new(REDBRICK_TEXTURE_WIDTH, REDBRICK_TEXTURE_HEIGHT, data)
 }
 
 const REDBRICK_TEXTURE_WIDTH: usize = 64;
@@ -1436,3 +1938,260 @@ const REDBRICK_TEXTURE: [u8; 16384] = [
     0x54, 0x54, 0x54, 0xff, 0x54, 0x54, 0x54, 0xff, 0x54, 0x54, 0x54, 0xff,
     0x54, 0x54, 0x54, 0xff,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAP_MODES: [WrapMode; 3] = [
+        WrapMode::Repeat,
+        WrapMode::ClampToEdge,
+        WrapMode::MirroredRepeat,
+    ];
+    const OUT_OF_RANGE_UVS: [f32; 9] = [
+        -3.7,
+        -1.0,
+        0.0,
+        1.0,
+        5.2,
+        f32::NAN,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::MAX,
+    ];
+
+    /// Loads a fixture from test_assets/png and returns its full resolution
+    /// texels
+    fn load_png_fixture(file_name: &str) -> Vec<u32> {
+        let path = format!(
+            "{}/test_assets/png/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            file_name
+        );
+        let texture = load_texture(&path).unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+
+        texture.levels[0].data.clone()
+    }
+
+    /// A 3x2 texture whose texels are all different
+    fn create_test_texture() -> Texture {
+        Texture::new(
+            3,
+            2,
+            vec![
+                0xFF000000, 0xFF000001, 0xFF000002, 0xFF000100, 0xFF000101,
+                0xFF000102,
+            ],
+        )
+    }
+
+    #[test]
+    fn wrap_repeat() {
+        let wrapped: Vec<usize> = (-4..5)
+            .map(|coordinate| WrapMode::Repeat.wrap(coordinate, 3))
+            .collect();
+        assert_eq!(wrapped, [2, 0, 1, 2, 0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn wrap_clamp_to_edge() {
+        let wrapped: Vec<usize> = (-4..5)
+            .map(|coordinate| WrapMode::ClampToEdge.wrap(coordinate, 3))
+            .collect();
+        assert_eq!(wrapped, [0, 0, 0, 0, 0, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn wrap_mirrored_repeat() {
+        let wrapped: Vec<usize> = (-4..5)
+            .map(|coordinate| WrapMode::MirroredRepeat.wrap(coordinate, 3))
+            .collect();
+        assert_eq!(wrapped, [2, 2, 1, 0, 0, 1, 2, 2, 1]);
+    }
+
+    #[test]
+    fn wrap_extreme_coordinates_stay_in_range() {
+        for wrap_mode in WRAP_MODES {
+            for size in [1, 2, 3, 1024] {
+                for coordinate in [i32::MIN, i32::MIN + 1, -1, i32::MAX] {
+                    assert!(wrap_mode.wrap(coordinate, size) < size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sample_out_of_range_uvs_never_panics() {
+        let texture = create_test_texture();
+        let texels = &texture.levels[0].data;
+        let derivatives = [
+            TextureUv { u: 0.0, v: 0.0 },
+            TextureUv { u: 0.01, v: 0.0 },
+            TextureUv { u: 10.0, v: -3.0 },
+            TextureUv {
+                u: f32::NAN,
+                v: f32::INFINITY,
+            },
+        ];
+
+        for wrap_mode in WRAP_MODES {
+            for filter_mode in [FilterMode::Nearest, FilterMode::Bilinear] {
+                for mipmap_mode in [
+                    MipmapMode::Disabled,
+                    MipmapMode::Nearest,
+                    MipmapMode::Linear,
+                ] {
+                    for max_anisotropy in [1, 8] {
+                        let sampler = Sampler {
+                            filter_mode,
+                            mipmap_mode,
+                            max_anisotropy,
+                            wrap_u: wrap_mode,
+                            wrap_v: wrap_mode,
+                        };
+
+                        for u in OUT_OF_RANGE_UVS {
+                            for v in OUT_OF_RANGE_UVS {
+                                for derivative in &derivatives {
+                                    sampler.sample(
+                                        &texture,
+                                        &TextureUv { u, v },
+                                        derivative,
+                                        derivative,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Unfiltered samples of the full resolution texture are texels of it
+        let sampler = Sampler::default();
+        let no_derivative = TextureUv::default();
+        for u in OUT_OF_RANGE_UVS {
+            for v in OUT_OF_RANGE_UVS {
+                let color = sampler.sample(
+                    &texture,
+                    &TextureUv { u, v },
+                    &no_derivative,
+                    &no_derivative,
+                );
+                assert!(texels.contains(&color));
+            }
+        }
+    }
+
+    #[test]
+    fn sample_wraps_per_axis() {
+        let texture = create_test_texture();
+        let no_derivative = TextureUv::default();
+        let sampler = Sampler {
+            wrap_u: WrapMode::ClampToEdge,
+            wrap_v: WrapMode::Repeat,
+            ..Sampler::default()
+        };
+
+        // u clamps to the last column and v repeats back to the first row
+        let color = sampler.sample(
+            &texture,
+            &TextureUv { u: 5.2, v: 1.25 },
+            &no_derivative,
+            &no_derivative,
+        );
+        assert_eq!(color, 0xFF000002);
+    }
+
+    #[test]
+    fn decode_png_grayscale() {
+        let black = 0xFF000000;
+        let white = 0xFFFFFFFF;
+        let ramp = [black, 0xFF555555, 0xFFAAAAAA, white];
+        assert_eq!(
+            load_png_fixture("gray_1.png"),
+            [black, white, white, black]
+        );
+        assert_eq!(load_png_fixture("gray_2.png"), ramp);
+        assert_eq!(load_png_fixture("gray_4.png"), ramp);
+        assert_eq!(
+            load_png_fixture("gray_8.png"),
+            [black, 0xFF404040, 0xFF808080, white]
+        );
+        assert_eq!(
+            load_png_fixture("gray_16.png"),
+            [black, 0xFF404040, 0xFF808080, white]
+        );
+    }
+
+    #[test]
+    fn decode_png_grayscale_alpha() {
+        assert_eq!(
+            load_png_fixture("gray_alpha_8.png"),
+            [0xFF101010, 0x80202020, 0x00303030, 0x40404040]
+        );
+    }
+
+    #[test]
+    fn decode_png_rgb() {
+        assert_eq!(
+            load_png_fixture("rgb_8.png"),
+            [0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF123456]
+        );
+    }
+
+    #[test]
+    fn decode_png_rgba() {
+        assert_eq!(
+            load_png_fixture("rgba_8.png"),
+            [0xFFFF0000, 0x8000FF00, 0x000000FF, 0x78123456]
+        );
+        assert_eq!(
+            load_png_fixture("rgba_16.png"),
+            [0xFFFF0000, 0x8000FF00, 0x000000FF, 0x78123456]
+        );
+    }
+
+    #[test]
+    fn decode_png_palette() {
+        assert_eq!(
+            load_png_fixture("palette.png"),
+            [0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF123456]
+        );
+        // Palette entries past the end of the tRNS chunk are opaque
+        assert_eq!(
+            load_png_fixture("palette_trns.png"),
+            [0xFFFF0000, 0x8000FF00, 0x000000FF, 0xFF123456]
+        );
+    }
+
+    #[test]
+    fn alpha_mode_follows_texel_alpha() {
+        let alpha_mode_of = |file_name| {
+            let path = format!(
+                "{}/test_assets/png/{}",
+                env!("CARGO_MANIFEST_DIR"),
+                file_name
+            );
+            load_texture(&path).unwrap().alpha_mode
+        };
+        assert!(alpha_mode_of("rgb_8.png") == AlphaMode::Opaque);
+        assert!(alpha_mode_of("rgba_8.png") == AlphaMode::Blend);
+
+        let cutout = Texture::new(2, 1, vec![0xFF123456, 0x00000000]);
+        assert!(
+            cutout.alpha_mode
+                == AlphaMode::Test {
+                    cutoff: DEFAULT_ALPHA_CUTOFF
+                }
+        );
+    }
+
+    #[test]
+    fn decode_png_rejects_corrupt_data() {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend_from_slice(&[0; 16]);
+        assert!(decode_png(&data).is_err());
+    }
+}
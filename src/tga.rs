@@ -0,0 +1,212 @@
+use crate::texture::{pack_argb, read_u16_le, Texture};
+
+const HEADER_SIZE: usize = 18;
+
+/// Decodes an uncompressed or run-length encoded true color or grayscale TGA
+/// image
+pub fn decode_tga(data: &[u8]) -> Result<Texture, String> {
+    if data.len() < HEADER_SIZE {
+        return Err("TGA header is truncated".to_string());
+    }
+
+    let id_length = data[0] as usize;
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let color_map_length = read_u16_le(data, 5)? as usize;
+    let color_map_entry_size = data[7] as usize;
+    let width = read_u16_le(data, 12)? as usize;
+    let height = read_u16_le(data, 14)? as usize;
+    let pixel_depth = data[16];
+    let descriptor = data[17];
+
+    if width == 0 || height == 0 {
+        return Err(format!("Bad TGA dimensions {}x{}", width, height));
+    }
+
+    // Image types 2 and 3 are uncompressed true color and grayscale. Adding 8
+    // gives their run-length encoded versions.
+    let (is_grayscale, is_rle) = match image_type {
+        2 => (false, false),
+        3 => (true, false),
+        10 => (false, true),
+        11 => (true, true),
+        _ => return Err(format!("Unsupported TGA image type {}", image_type)),
+    };
+
+    let bytes_per_pixel = match (is_grayscale, pixel_depth) {
+        (true, 8) => 1,
+        (true, 16) => 2,
+        (false, 15) | (false, 16) => 2,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => {
+            return Err(format!(
+                "Unsupported TGA pixel depth {} for image type {}",
+                pixel_depth, image_type
+            ))
+        }
+    };
+
+    // Skip the image ID and any color map, which true color images may
+    // include but don't use
+    let mut offset = HEADER_SIZE + id_length;
+    if color_map_type != 0 {
+        offset += color_map_length * color_map_entry_size.div_ceil(8);
+    }
+
+    let pixel_count = width * height;
+    let mut pixels = vec![];
+    while pixels.len() < pixel_count {
+        if is_rle {
+            // Each packet starts with a header byte. If the high bit is set
+            // the next pixel is repeated, otherwise raw pixels follow. The low
+            // 7 bits are the number of pixels minus one.
+            let packet_header = match data.get(offset) {
+                Some(packet_header) => *packet_header,
+                None => return Err("TGA pixel data is truncated".to_string()),
+            };
+            offset += 1;
+            let packet_length = (packet_header & 0x7F) as usize + 1;
+
+            if packet_header & 0x80 != 0 {
+                let pixel = read_pixel(
+                    data,
+                    offset,
+                    bytes_per_pixel,
+                    is_grayscale,
+                    pixel_depth,
+                )?;
+                offset += bytes_per_pixel;
+                for _ in 0..packet_length {
+                    pixels.push(pixel);
+                }
+            } else {
+                for _ in 0..packet_length {
+                    pixels.push(read_pixel(
+                        data,
+                        offset,
+                        bytes_per_pixel,
+                        is_grayscale,
+                        pixel_depth,
+                    )?);
+                    offset += bytes_per_pixel;
+                }
+            }
+        } else {
+            pixels.push(read_pixel(
+                data,
+                offset,
+                bytes_per_pixel,
+                is_grayscale,
+                pixel_depth,
+            )?);
+            offset += bytes_per_pixel;
+        }
+    }
+    // A run may extend past the last pixel of a malformed image
+    pixels.truncate(pixel_count);
+
+    // Bits 4 and 5 of the descriptor give the origin of the image. By default
+    // rows run from the bottom up and pixels run from left to right.
+    let right_to_left = descriptor & 0x10 != 0;
+    let top_to_bottom = descriptor & 0x20 != 0;
+
+    let mut argb_data = Vec::with_capacity(pixel_count);
+    for y in 0..height {
+        let source_y = if top_to_bottom { y } else { height - 1 - y };
+        for x in 0..width {
+            let source_x = if right_to_left { width - 1 - x } else { x };
+            argb_data.push(pixels[source_y * width + source_x]);
+        }
+    }
+
+    Ok(Texture::new(width, height, argb_data))
+}
+
+/// Reads a single pixel at 'offset' and converts it to ARGB
+fn read_pixel(
+    data: &[u8],
+    offset: usize,
+    bytes_per_pixel: usize,
+    is_grayscale: bool,
+    pixel_depth: u8,
+) -> Result<u32, String> {
+    let pixel = match data.get(offset..(offset + bytes_per_pixel)) {
+        Some(pixel) => pixel,
+        None => return Err("TGA pixel data is truncated".to_string()),
+    };
+
+    let argb = match (is_grayscale, pixel) {
+        (true, [gray]) => pack_argb(0xFF, *gray, *gray, *gray),
+        (true, [gray, alpha]) => pack_argb(*alpha, *gray, *gray, *gray),
+        (false, [low, high]) => {
+            // 5 bits per channel packed as ARRRRRGG GGGBBBBB
+            let value = u16::from_le_bytes([*low, *high]);
+            let expand = |channel: u16| ((channel & 0x1F) * 255 / 31) as u8;
+            let alpha = if pixel_depth == 16 && value & 0x8000 == 0 {
+                0x00
+            } else {
+                0xFF
+            };
+            pack_argb(
+                alpha,
+                expand(value >> 10),
+                expand(value >> 5),
+                expand(value),
+            )
+        }
+        // TGA stores channels as BGR(A)
+        (false, [blue, green, red]) => pack_argb(0xFF, *red, *green, *blue),
+        (false, [blue, green, red, alpha]) => {
+            pack_argb(*alpha, *red, *green, *blue)
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(argb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a TGA header for an image with no ID or color map
+    fn create_header(image_type: u8, width: u16, height: u16) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = 24;
+        header
+    }
+
+    #[test]
+    fn decode_uncompressed_tga() {
+        // Rows run from the bottom up
+        let mut data = create_header(2, 1, 2);
+        data.extend_from_slice(&[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+        let texture = decode_tga(&data).unwrap();
+        assert_eq!(texture.levels[0].data, [0xFFFF0000, 0xFF0000FF]);
+    }
+
+    #[test]
+    fn decode_rle_tga() {
+        // A run of 2 pixels followed by a raw packet of 1 pixel
+        let mut data = create_header(10, 3, 1);
+        data[17] = 0x20;
+        data.extend_from_slice(&[0x81, 0x00, 0xFF, 0x00]);
+        data.extend_from_slice(&[0x00, 0x56, 0x34, 0x12]);
+        let texture = decode_tga(&data).unwrap();
+        assert_eq!(
+            texture.levels[0].data,
+            [0xFF00FF00, 0xFF00FF00, 0xFF123456]
+        );
+    }
+
+    #[test]
+    fn decode_tga_rejects_zero_dimensions() {
+        assert!(decode_tga(&create_header(2, 0, 0)).is_err());
+        assert!(decode_tga(&create_header(2, 4, 0)).is_err());
+        assert!(decode_tga(&create_header(10, 0, 4)).is_err());
+    }
+}
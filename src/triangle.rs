@@ -1,4 +1,120 @@
-use crate::{texture::TextureUv, vector::Vector4};
+use crate::{
+    asset_manager::TextureHandle,
+    color::Color,
+    material::Material,
+    texture::TextureUv,
+    vector::{Vector3, Vector4},
+};
+
+/// The maximum number of per-vertex attributes a triangle can carry
+pub const MAX_VARYINGS: usize = 16;
+
+/// An arbitrary list of per-vertex attributes (normals, colors, positions,
+/// extra UV sets, etc.). Varyings are linearly interpolated when a triangle is
+/// clipped and perspective-correctly interpolated for every rasterized pixel.
+#[derive(Clone, Copy, Default)]
+pub struct Varyings {
+    pub values: [f32; MAX_VARYINGS],
+    pub len: usize,
+}
+
+impl Varyings {
+    /// Appends a single value and returns its index
+    pub fn push(&mut self, value: f32) -> usize {
+        assert!(self.len < MAX_VARYINGS, "Too many varyings");
+
+        let index = self.len;
+        self.values[index] = value;
+        self.len += 1;
+
+        index
+    }
+
+    /// Appends the three components of a vector and returns the index of the
+    /// first component
+    pub fn push_vector3(&mut self, v: &Vector3) -> usize {
+        let index = self.push(v.x);
+        self.push(v.y);
+        self.push(v.z);
+
+        index
+    }
+
+    /// Appends the RGB channels of a color and returns the index of the red
+    /// channel
+    pub fn push_color(&mut self, color: &Color) -> usize {
+        let index = self.push(color.r);
+        self.push(color.g);
+        self.push(color.b);
+
+        index
+    }
+
+    pub fn get(&self, index: usize) -> f32 {
+        self.values[index]
+    }
+
+    pub fn get_vector3(&self, index: usize) -> Vector3 {
+        Vector3 {
+            x: self.values[index],
+            y: self.values[index + 1],
+            z: self.values[index + 2],
+        }
+    }
+
+    /// Gets an opaque color that was stored with push_color
+    pub fn get_color(&self, index: usize) -> Color {
+        Color::rgb(
+            self.values[index],
+            self.values[index + 1],
+            self.values[index + 2],
+        )
+    }
+
+    /// Linearly interpolates between a and b. A t of 0.0 returns a and a t of
+    /// 1.0 returns b.
+    pub fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+        let mut result = Self {
+            len: a.len,
+            ..Default::default()
+        };
+
+        for index in 0..a.len {
+            result.values[index] =
+                a.values[index] + t * (b.values[index] - a.values[index]);
+        }
+
+        result
+    }
+
+    /// Interpolates varyings across a triangle using barycentric coordinates.
+    /// Each vertex's values are weighted by the reciprocal of its w so that the
+    /// interpolation is correct after the perspective divide.
+    pub fn interpolate_perspective(
+        varyings: [&Self; 3],
+        reciprocal_ws: [f32; 3],
+        barycentric: [f32; 3],
+        interpolated_reciprocal_w: f32,
+    ) -> Self {
+        let mut result = Self {
+            len: varyings[0].len,
+            ..Default::default()
+        };
+
+        for index in 0..result.len {
+            let interpolated = varyings[0].values[index]
+                * reciprocal_ws[0]
+                * barycentric[0]
+                + varyings[1].values[index] * reciprocal_ws[1] * barycentric[1]
+                + varyings[2].values[index] * reciprocal_ws[2] * barycentric[2];
+
+            // Undo reciprocal
+            result.values[index] = interpolated / interpolated_reciprocal_w;
+        }
+
+        result
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct Face {
@@ -8,29 +124,47 @@ pub struct Face {
     pub a_uv: usize,
     pub b_uv: usize,
     pub c_uv: usize,
+    pub a_normal: usize,
+    pub b_normal: usize,
+    pub c_normal: usize,
+    pub material: usize,
     pub color: u32,
 }
 
 #[derive(Clone, Default)]
 pub struct Triangle {
     pub points: [Vector4; 3],
-    pub texture_handle: usize,
+    pub texture_handle: TextureHandle,
     pub texel_coordinates: [TextureUv; 3],
+    pub varyings: [Varyings; 3],
+    pub material: Material,
     pub color: u32,
-    pub light_intensity: f32,
+    /// Light reaching the face, used for flat shading
+    pub light_color: Color,
 }
 
+/// A single vertex of a triangle along with all of its attributes
+pub type TriangleVertex = (Vector4, TextureUv, Varyings);
+
 /// sorts the triangle vertex data by their y coordinates. Ascending order.
 pub fn get_sorted_triangle_vertices(
     triangle: &Triangle,
-) -> (
-    (Vector4, TextureUv),
-    (Vector4, TextureUv),
-    (Vector4, TextureUv),
-) {
-    let vertex0 = (&triangle.points[0], &triangle.texel_coordinates[0]);
-    let vertex1 = (&triangle.points[1], &triangle.texel_coordinates[1]);
-    let vertex2 = (&triangle.points[2], &triangle.texel_coordinates[2]);
+) -> (TriangleVertex, TriangleVertex, TriangleVertex) {
+    let vertex0 = (
+        &triangle.points[0],
+        &triangle.texel_coordinates[0],
+        &triangle.varyings[0],
+    );
+    let vertex1 = (
+        &triangle.points[1],
+        &triangle.texel_coordinates[1],
+        &triangle.varyings[1],
+    );
+    let vertex2 = (
+        &triangle.points[2],
+        &triangle.texel_coordinates[2],
+        &triangle.varyings[2],
+    );
 
     let (vertex0, vertex1) = if vertex0.0.y > vertex1.0.y {
         (vertex1, vertex0)
@@ -49,8 +183,8 @@ pub fn get_sorted_triangle_vertices(
     };
 
     (
-        (vertex0.0.clone(), vertex0.1.clone()),
-        (vertex1.0.clone(), vertex1.1.clone()),
-        (vertex2.0.clone(), vertex2.1.clone()),
+        (vertex0.0.clone(), vertex0.1.clone(), *vertex0.2),
+        (vertex1.0.clone(), vertex1.1.clone(), *vertex1.2),
+        (vertex2.0.clone(), vertex2.1.clone(), *vertex2.2),
     )
 }